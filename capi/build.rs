@@ -0,0 +1,24 @@
+//! Generates the C header for the khill C ABI from the `extern "C"` functions in `src/lib.rs`.
+//!
+//! The header is written to `OUT_DIR`, and a test verifies that the committed copy in `include/khill.h`
+//! matches it. The committed header is regenerated from this directory with
+//! `cbindgen --config cbindgen.toml --output include/khill.h`.
+
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set"));
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).expect("Invalid cbindgen.toml");
+
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Failed to generate C header")
+        .write_to_file(out_dir.join("khill.h"));
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
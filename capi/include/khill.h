@@ -0,0 +1,119 @@
+/* Generated by cbindgen from capi/src/lib.rs. Do not edit manually. */
+
+#ifndef KHILL_H
+#define KHILL_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Status returned by functions which succeed.
+#define KHILL_OK 0
+
+// Status returned by functions which fail.
+#define KHILL_ERROR -1
+
+// FracMinHash sketch of a single genome.
+//
+// Sketches are created with `khill_sketch_new`, have sequences added until they are finalized with
+// `khill_sketch_finalize`, and must be released with `khill_sketch_free`.
+typedef struct KhillSketch KhillSketch;
+
+// K-Hill number and related statistics for a set of genomes.
+typedef struct KhillResult {
+  // K-Hill number (effective number of genomes)
+  double khill;
+  double alpha_entropy;
+  double gamma_entropy;
+  double beta_entropy;
+  // Effective number of k-mers within a genome
+  double alpha_diversity;
+  // Effective number of k-mers across all genomes
+  double gamma_diversity;
+} KhillResult;
+
+// Contribution of a single genome to the K-Hill number.
+typedef struct KhillGenomeComponent {
+  double entropy;
+  double kl_divergence;
+  double weight;
+  // K-Hill number of the set of genomes with this genome left out (NaN for a single genome)
+  double khill_without_genome;
+} KhillGenomeComponent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a sketch handle for a genome.
+//
+// Returns NULL if the genome identifier is not valid UTF-8, the k-mer length is not in [1, 64], or the
+// scale is 0. Sketches are weighted by the number of occurrences of each k-mer unless `weighted` is false,
+// and k-mers with ambiguous bases are skipped unless `skip_ambiguous` is false, in which case ambiguous
+// bases are treated as an A.
+//
+// # Safety
+//
+// `genome_id` must be a valid NUL-terminated string.
+struct KhillSketch *khill_sketch_new(const char *genome_id,
+                                     uint8_t k,
+                                     uint64_t scale,
+                                     bool weighted,
+                                     bool skip_ambiguous);
+
+// Add a nucleotide sequence without line breaks (e.g. a contig) to a sketch which has not been finalized.
+//
+// # Safety
+//
+// `sketch` must be a handle returned by `khill_sketch_new` and `seq` must point to `len` readable bytes.
+int khill_sketch_add_sequence(struct KhillSketch *sketch,
+                              const uint8_t *seq,
+                              size_t len);
+
+// Finalize a sketch once all sequences of the genome have been added.
+//
+// # Safety
+//
+// `sketch` must be a handle returned by `khill_sketch_new`.
+int khill_sketch_finalize(struct KhillSketch *sketch);
+
+// Number of unique k-mer hashes retained by a sketch, or 0 if `sketch` is NULL.
+//
+// # Safety
+//
+// `sketch` must be NULL or a handle returned by `khill_sketch_new`.
+uint64_t khill_sketch_num_hashes(const struct KhillSketch *sketch);
+
+// Release a sketch handle. Passing NULL has no effect.
+//
+// # Safety
+//
+// `sketch` must be NULL or a handle returned by `khill_sketch_new` which has not already been released.
+void khill_sketch_free(struct KhillSketch *sketch);
+
+// Calculate the K-Hill number over a set of finalized sketches.
+//
+// Sketches must have distinct genome identifiers and have been created with identical parameters. If
+// `components` is not NULL, the contribution of each genome is written to `components[i]` in the same
+// order as `sketches`. Calculations use `threads` threads, or the global rayon thread pool if `threads` is 0.
+//
+// # Safety
+//
+// `sketches` must point to `num_sketches` valid sketch handles, `result` must be writable, and
+// `components` must be NULL or point to `num_sketches` writable elements.
+int khill_compute(const struct KhillSketch *const *sketches,
+                  size_t num_sketches,
+                  size_t threads,
+                  struct KhillResult *result,
+                  struct KhillGenomeComponent *components);
+
+// Description of the most recent error on the calling thread, or NULL if no error has occurred.
+//
+// The returned string is owned by the library and remains valid until the next failing call on the same thread.
+const char *khill_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* KHILL_H */
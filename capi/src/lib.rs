@@ -0,0 +1,404 @@
+//! C ABI for the khill library.
+//!
+//! This crate builds a shared and static library allowing tools written in C or C++ to sketch genomes
+//! and calculate the K-Hill number in-process. Genomes are represented by opaque sketch handles which
+//! wrap a `FracMinHash` sketcher. Sequences are added to a handle, which is then finalized into the
+//! retained k-mer hashes, and the K-Hill number is calculated over a set of finalized handles.
+//!
+//! Functions return `KHILL_OK` on success and `KHILL_ERROR` on failure, or NULL for functions returning
+//! a pointer. A description of the most recent error on the calling thread is available from
+//! `khill_last_error`. The C header `include/khill.h` is generated by cbindgen when this crate is built.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::ThreadPoolBuilder;
+use rustc_hash::FxHashMap;
+
+use khill::{khill_from_hashes, FracMinHash, Hashes, KHillResult, SketchParams};
+
+/// Status returned by functions which succeed.
+pub const KHILL_OK: c_int = 0;
+
+/// Status returned by functions which fail.
+pub const KHILL_ERROR: c_int = -1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// FracMinHash sketch of a single genome.
+///
+/// Sketches are created with `khill_sketch_new`, have sequences added until they are finalized with
+/// `khill_sketch_finalize`, and must be released with `khill_sketch_free`.
+pub struct KhillSketch {
+    genome_id: String,
+    params: SketchParams,
+    state: SketchState,
+}
+
+/// Stage of a sketch handle.
+enum SketchState {
+    Building(FracMinHash),
+    Finalized(Hashes),
+}
+
+/// K-Hill number and related statistics for a set of genomes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KhillResult {
+    /// K-Hill number (effective number of genomes)
+    pub khill: f64,
+    pub alpha_entropy: f64,
+    pub gamma_entropy: f64,
+    pub beta_entropy: f64,
+    /// Effective number of k-mers within a genome
+    pub alpha_diversity: f64,
+    /// Effective number of k-mers across all genomes
+    pub gamma_diversity: f64,
+}
+
+/// Contribution of a single genome to the K-Hill number.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KhillGenomeComponent {
+    pub entropy: f64,
+    pub kl_divergence: f64,
+    pub weight: f64,
+    /// K-Hill number of the set of genomes with this genome left out (NaN for a single genome)
+    pub khill_without_genome: f64,
+}
+
+/// Create a sketch handle for a genome.
+///
+/// Returns NULL if the genome identifier is not valid UTF-8, the k-mer length is not in [1, 64], or the
+/// scale is 0. Sketches are weighted by the number of occurrences of each k-mer unless `weighted` is false,
+/// and k-mers with ambiguous bases are skipped unless `skip_ambiguous` is false, in which case ambiguous
+/// bases are treated as an A.
+///
+/// # Safety
+///
+/// `genome_id` must be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn khill_sketch_new(
+    genome_id: *const c_char,
+    k: u8,
+    scale: u64,
+    weighted: bool,
+    skip_ambiguous: bool,
+) -> *mut KhillSketch {
+    let sketch = ffi_call(|| {
+        if genome_id.is_null() {
+            bail!("Genome identifier is NULL");
+        }
+        let genome_id = unsafe { CStr::from_ptr(genome_id) }
+            .to_str()
+            .context("Genome identifier is not valid UTF-8")?;
+        let params = SketchParams::try_new(k, scale, weighted, skip_ambiguous)?;
+        Ok(KhillSketch {
+            genome_id: genome_id.to_string(),
+            state: SketchState::Building(params.create_sketcher()),
+            params,
+        })
+    });
+
+    sketch.map_or(ptr::null_mut(), |sketch| Box::into_raw(Box::new(sketch)))
+}
+
+/// Add a nucleotide sequence without line breaks (e.g. a contig) to a sketch which has not been finalized.
+///
+/// # Safety
+///
+/// `sketch` must be a handle returned by `khill_sketch_new` and `seq` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn khill_sketch_add_sequence(sketch: *mut KhillSketch, seq: *const u8, len: usize) -> c_int {
+    ffi_status(|| {
+        let sketch = unsafe { sketch_mut(sketch)? };
+        let SketchState::Building(sketcher) = &mut sketch.state else {
+            bail!("Sketch of {} has already been finalized", sketch.genome_id);
+        };
+
+        if len > 0 {
+            if seq.is_null() {
+                bail!("Sequence is NULL");
+            }
+            sketcher.process_bytes(unsafe { slice::from_raw_parts(seq, len) });
+        }
+
+        Ok(())
+    })
+}
+
+/// Finalize a sketch once all sequences of the genome have been added.
+///
+/// # Safety
+///
+/// `sketch` must be a handle returned by `khill_sketch_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn khill_sketch_finalize(sketch: *mut KhillSketch) -> c_int {
+    ffi_status(|| {
+        let sketch = unsafe { sketch_mut(sketch)? };
+        let placeholder = SketchState::Finalized(Hashes::default());
+        match std::mem::replace(&mut sketch.state, placeholder) {
+            SketchState::Building(sketcher) => {
+                sketch.state = SketchState::Finalized(sketcher.to_hashes());
+                Ok(())
+            }
+            finalized => {
+                sketch.state = finalized;
+                bail!("Sketch of {} has already been finalized", sketch.genome_id)
+            }
+        }
+    })
+}
+
+/// Number of unique k-mer hashes retained by a sketch, or 0 if `sketch` is NULL.
+///
+/// # Safety
+///
+/// `sketch` must be NULL or a handle returned by `khill_sketch_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn khill_sketch_num_hashes(sketch: *const KhillSketch) -> u64 {
+    match unsafe { sketch.as_ref() } {
+        Some(KhillSketch { state: SketchState::Building(sketcher), .. }) => sketcher.unique_hash_count(),
+        Some(KhillSketch { state: SketchState::Finalized(hashes), .. }) => hashes.len() as u64,
+        None => 0,
+    }
+}
+
+/// Release a sketch handle. Passing NULL has no effect.
+///
+/// # Safety
+///
+/// `sketch` must be NULL or a handle returned by `khill_sketch_new` which has not already been released.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn khill_sketch_free(sketch: *mut KhillSketch) {
+    if !sketch.is_null() {
+        drop(unsafe { Box::from_raw(sketch) });
+    }
+}
+
+/// Calculate the K-Hill number over a set of finalized sketches.
+///
+/// Sketches must have distinct genome identifiers and have been created with identical parameters. If
+/// `components` is not NULL, the contribution of each genome is written to `components[i]` in the same
+/// order as `sketches`. Calculations use `threads` threads, or the global rayon thread pool if `threads` is 0.
+///
+/// # Safety
+///
+/// `sketches` must point to `num_sketches` valid sketch handles, `result` must be writable, and
+/// `components` must be NULL or point to `num_sketches` writable elements.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn khill_compute(
+    sketches: *const *const KhillSketch,
+    num_sketches: usize,
+    threads: usize,
+    result: *mut KhillResult,
+    components: *mut KhillGenomeComponent,
+) -> c_int {
+    ffi_status(|| {
+        if sketches.is_null() || num_sketches == 0 {
+            bail!("No sketches specified");
+        }
+        if result.is_null() {
+            bail!("Result is NULL");
+        }
+
+        let handles = unsafe { slice::from_raw_parts(sketches, num_sketches) };
+        let mut genome_hashes = FxHashMap::default();
+        for &handle in handles {
+            let sketch = unsafe { handle.as_ref() }.ok_or_else(|| anyhow!("Sketch is NULL"))?;
+            let SketchState::Finalized(hashes) = &sketch.state else {
+                bail!("Sketch of {} has not been finalized", sketch.genome_id);
+            };
+
+            let first = unsafe { &*handles[0] };
+            sketch.params.check_compatibility(&first.params)
+                .context(format!("Incompatible sketch parameters for {}", sketch.genome_id))?;
+            if genome_hashes.insert(sketch.genome_id.as_str(), hashes).is_some() {
+                bail!("Genome identifier '{}' is used by multiple sketches", sketch.genome_id);
+            }
+        }
+
+        let genome_hashes: FxHashMap<String, &Hashes> = genome_hashes.into_iter()
+            .map(|(genome_id, hashes)| (genome_id.to_string(), hashes))
+            .collect();
+        let khill_result = if threads == 0 {
+            khill_from_hashes(&genome_hashes, &[])
+        } else {
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?
+                .install(|| khill_from_hashes(&genome_hashes, &[]))
+        };
+
+        unsafe { result.write(summarize(&khill_result)) };
+        if !components.is_null() {
+            for (i, &handle) in handles.iter().enumerate() {
+                let genome_id = unsafe { &(*handle).genome_id };
+                let component = &khill_result.genome_components[genome_id];
+                unsafe {
+                    components.add(i).write(KhillGenomeComponent {
+                        entropy: component.entropy,
+                        kl_divergence: component.kl_divergence,
+                        weight: component.weight,
+                        khill_without_genome: khill_result.leave_one_out_khill[genome_id],
+                    })
+                };
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Description of the most recent error on the calling thread, or NULL if no error has occurred.
+///
+/// The returned string is owned by the library and remains valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn khill_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ref().map_or(ptr::null(), |error| error.as_ptr()))
+}
+
+/// Summary statistics of a K-Hill result.
+fn summarize(result: &KHillResult) -> KhillResult {
+    KhillResult {
+        khill: result.khill(),
+        alpha_entropy: result.alpha_entropy,
+        gamma_entropy: result.gamma_entropy,
+        beta_entropy: result.beta_entropy,
+        alpha_diversity: result.alpha_diversity(),
+        gamma_diversity: result.gamma_diversity(),
+    }
+}
+
+/// Dereference a sketch handle, reporting an error if it is NULL.
+///
+/// # Safety
+///
+/// `sketch` must be NULL or a handle returned by `khill_sketch_new`.
+unsafe fn sketch_mut<'a>(sketch: *mut KhillSketch) -> Result<&'a mut KhillSketch> {
+    unsafe { sketch.as_mut() }.ok_or_else(|| anyhow!("Sketch is NULL"))
+}
+
+/// Run a function, recording any error or panic so it can be retrieved with `khill_last_error`.
+///
+/// Panics must not unwind across the C ABI, so they are reported as errors.
+fn ffi_call<T>(f: impl FnOnce() -> Result<T>) -> Option<T> {
+    let error = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return Some(value),
+        Ok(Err(error)) => format!("{error:#}"),
+        Err(_) => "Unexpected internal error in khill".to_string(),
+    };
+
+    let error = CString::new(error.replace('\0', " ")).expect("NUL bytes removed from error");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(error));
+    None
+}
+
+/// Run a function returning a status code, recording any error so it can be retrieved with `khill_last_error`.
+fn ffi_status(f: impl FnOnce() -> Result<()>) -> c_int {
+    match ffi_call(f) {
+        Some(()) => KHILL_OK,
+        None => KHILL_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use khill::{khill_from_sketches, Sketch};
+
+    fn new_sketch(genome_id: &str, seqs: &[&[u8]], k: u8) -> *mut KhillSketch {
+        let genome_id = CString::new(genome_id).unwrap();
+        let sketch = unsafe { khill_sketch_new(genome_id.as_ptr(), k, 1, true, true) };
+        assert!(!sketch.is_null());
+        for seq in seqs {
+            assert_eq!(unsafe { khill_sketch_add_sequence(sketch, seq.as_ptr(), seq.len()) }, KHILL_OK);
+        }
+        sketch
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(khill_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_header_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/khill.h"));
+        let committed = include_str!("../include/khill.h");
+        assert!(
+            generated == committed,
+            "include/khill.h is out of date; regenerate it with `cbindgen --config cbindgen.toml --output include/khill.h`"
+        );
+    }
+
+    #[test]
+    fn test_khill_compute() {
+        let seqs: [&[u8]; 2] = [b"ACGTACGTACGT", b"GGATCCAAGTTT"];
+        let sketches = [new_sketch("genome1", &seqs[..1], 3), new_sketch("genome2", &seqs[1..], 3)];
+
+        // sketches must be finalized before K-Hill is calculated
+        let handles: Vec<*const KhillSketch> = sketches.iter().map(|&s| s as *const KhillSketch).collect();
+        let mut result = KhillResult::default();
+        let status = unsafe { khill_compute(handles.as_ptr(), 2, 1, &mut result, ptr::null_mut()) };
+        assert_eq!(status, KHILL_ERROR);
+        assert!(last_error().contains("has not been finalized"));
+
+        for &sketch in &sketches {
+            assert_eq!(unsafe { khill_sketch_finalize(sketch) }, KHILL_OK);
+        }
+        assert_eq!(unsafe { khill_sketch_finalize(sketches[0]) }, KHILL_ERROR);
+        assert_eq!(unsafe { khill_sketch_add_sequence(sketches[0], seqs[0].as_ptr(), 12) }, KHILL_ERROR);
+
+        let mut components = [KhillGenomeComponent::default(); 2];
+        let status = unsafe { khill_compute(handles.as_ptr(), 2, 2, &mut result, components.as_mut_ptr()) };
+        assert_eq!(status, KHILL_OK);
+
+        let params = SketchParams::new(3, 1, true, true);
+        let expected_sketches = [Sketch::from_seqs("genome1", &seqs[..1], &params), Sketch::from_seqs("genome2", &seqs[1..], &params)];
+        assert_eq!(unsafe { khill_sketch_num_hashes(sketches[0]) }, expected_sketches[0].hashes.len() as u64);
+        let expected = khill_from_sketches(&expected_sketches, &[]).unwrap();
+        assert_eq!(result, summarize(&expected));
+        assert_eq!(components[1].weight, expected.genome_components["genome2"].weight);
+        assert!((components[0].khill_without_genome - 1.0).abs() < 1e-12);
+
+        for sketch in sketches {
+            unsafe { khill_sketch_free(sketch) };
+        }
+    }
+
+    #[test]
+    fn test_invalid_sketches() {
+        let genome_id = CString::new("genome1").unwrap();
+        assert!(unsafe { khill_sketch_new(genome_id.as_ptr(), 0, 1, true, true) }.is_null());
+        assert!(last_error().contains("k-mer length"));
+        assert!(unsafe { khill_sketch_new(ptr::null(), 3, 1, true, true) }.is_null());
+
+        // sketches must have compatible parameters and distinct genome identifiers
+        let sketches = [
+            new_sketch("genome1", &[b"ACGTACGT"], 3),
+            new_sketch("genome1", &[b"GGATCCAA"], 3),
+            new_sketch("genome2", &[b"GGATCCAA"], 4),
+        ];
+        for &sketch in &sketches {
+            assert_eq!(unsafe { khill_sketch_finalize(sketch) }, KHILL_OK);
+        }
+
+        let mut result = KhillResult::default();
+        for (pair, error) in [([0, 1], "multiple sketches"), ([0, 2], "Incompatible sketch parameters")] {
+            let handles = pair.map(|i| sketches[i] as *const KhillSketch);
+            let status = unsafe { khill_compute(handles.as_ptr(), 2, 0, &mut result, ptr::null_mut()) };
+            assert_eq!(status, KHILL_ERROR);
+            assert!(last_error().contains(error));
+        }
+
+        for sketch in sketches {
+            unsafe { khill_sketch_free(sketch) };
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Python bindings for the khill library.
+//!
+//! This crate builds the `khill` Python extension module with PyO3. It exposes sketch parameters,
+//! sketching of genome files or in-memory sequences into k-mer hashes, and calculation of the
+//! K-Hill number from these hashes. Hashes are returned to Python as a dict mapping each retained
+//! k-mer hash to its count, so sketches can be stored, filtered, or combined in Python before
+//! calculating K-Hill. Calculations release the GIL and can be run on a dedicated thread pool.
+//!
+//! The extension module is built with maturin (e.g. `maturin develop --release` within this directory).
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rayon::ThreadPoolBuilder;
+use rustc_hash::FxHashMap;
+
+use khill::khill::load_sketch;
+use khill::{khill_from_hashes, khill_from_sketches, HillComponent, HillNumbers, KHillResult, Hashes, Sketch, SketchParams};
+
+/// Default length of k-mers, matching the khill command-line interface.
+const DEFAULT_K: u8 = 19;
+
+/// Default sketch scaling factor, matching the khill command-line interface.
+const DEFAULT_SCALE: u64 = 100;
+
+/// Parameters used to create FracMinHash sketches.
+#[pyclass(name = "SketchParams", module = "khill", frozen)]
+struct PySketchParams {
+    inner: SketchParams,
+}
+
+#[pymethods]
+impl PySketchParams {
+    #[new]
+    #[pyo3(signature = (k = DEFAULT_K, scale = DEFAULT_SCALE, weighted = true, skip_ambiguous = true))]
+    fn new(k: u8, scale: u64, weighted: bool, skip_ambiguous: bool) -> PyResult<Self> {
+        let inner = SketchParams::try_new(k, scale, weighted, skip_ambiguous)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+
+        Ok(PySketchParams { inner })
+    }
+
+    #[getter]
+    fn k(&self) -> u8 {
+        self.inner.k()
+    }
+
+    #[getter]
+    fn scale(&self) -> u64 {
+        self.inner.scale()
+    }
+
+    #[getter]
+    fn weighted(&self) -> bool {
+        self.inner.weighted()
+    }
+
+    #[getter]
+    fn skip_ambiguous(&self) -> bool {
+        self.inner.skip_ambiguous()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "SketchParams(k={}, scale={}, weighted={}, skip_ambiguous={})",
+            self.inner.k(),
+            self.inner.scale(),
+            py_bool(self.inner.weighted()),
+            py_bool(self.inner.skip_ambiguous())
+        )
+    }
+}
+
+/// Contribution of a single genome to the K-Hill number.
+#[pyclass(name = "HillComponent", module = "khill", frozen, get_all)]
+struct PyHillComponent {
+    entropy: f64,
+    kl_divergence: f64,
+    weight: f64,
+}
+
+#[pymethods]
+impl PyHillComponent {
+    /// Beta entropy contributed by the genome (weight multiplied by KL-divergence).
+    #[getter]
+    fn beta_entropy(&self) -> f64 {
+        self.weight * self.kl_divergence
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("entropy", self.entropy)?;
+        dict.set_item("kl_divergence", self.kl_divergence)?;
+        dict.set_item("weight", self.weight)?;
+        dict.set_item("beta_entropy", self.beta_entropy())?;
+        Ok(dict)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "HillComponent(entropy={}, kl_divergence={}, weight={})",
+            self.entropy, self.kl_divergence, self.weight
+        )
+    }
+}
+
+impl From<&HillComponent> for PyHillComponent {
+    fn from(component: &HillComponent) -> Self {
+        PyHillComponent {
+            entropy: component.entropy,
+            kl_divergence: component.kl_divergence,
+            weight: component.weight,
+        }
+    }
+}
+
+/// Alpha, gamma, and beta Hill numbers of order q.
+#[pyclass(name = "HillNumbers", module = "khill", frozen, get_all)]
+struct PyHillNumbers {
+    q: f64,
+    alpha: f64,
+    gamma: f64,
+    beta: f64,
+}
+
+#[pymethods]
+impl PyHillNumbers {
+    fn __repr__(&self) -> String {
+        format!("HillNumbers(q={}, alpha={}, gamma={}, beta={})", self.q, self.alpha, self.gamma, self.beta)
+    }
+}
+
+impl From<&HillNumbers> for PyHillNumbers {
+    fn from(hill: &HillNumbers) -> Self {
+        PyHillNumbers {
+            q: hill.q,
+            alpha: hill.alpha,
+            gamma: hill.gamma,
+            beta: hill.beta,
+        }
+    }
+}
+
+/// Results of the K-Hill method for a set of genomes.
+#[pyclass(name = "KHillResult", module = "khill", frozen)]
+struct PyKHillResult {
+    inner: KHillResult,
+}
+
+#[pymethods]
+impl PyKHillResult {
+    /// K-Hill number (effective number of genomes).
+    #[getter]
+    fn khill(&self) -> f64 {
+        self.inner.khill()
+    }
+
+    #[getter]
+    fn alpha_entropy(&self) -> f64 {
+        self.inner.alpha_entropy
+    }
+
+    #[getter]
+    fn gamma_entropy(&self) -> f64 {
+        self.inner.gamma_entropy
+    }
+
+    #[getter]
+    fn beta_entropy(&self) -> f64 {
+        self.inner.beta_entropy
+    }
+
+    #[getter]
+    fn alpha_diversity(&self) -> f64 {
+        self.inner.alpha_diversity()
+    }
+
+    #[getter]
+    fn gamma_diversity(&self) -> f64 {
+        self.inner.gamma_diversity()
+    }
+
+    /// Hill components of each genome keyed by genome identifier.
+    #[getter]
+    fn genome_components(&self) -> FxHashMap<String, PyHillComponent> {
+        self.inner.genome_components.iter()
+            .map(|(genome_id, component)| (genome_id.clone(), component.into()))
+            .collect()
+    }
+
+    /// Hill numbers of each requested order.
+    #[getter]
+    fn hill_numbers(&self) -> Vec<PyHillNumbers> {
+        self.inner.hill_numbers.iter().map(PyHillNumbers::from).collect()
+    }
+
+    /// K-Hill number with each genome left out, keyed by genome identifier.
+    #[getter]
+    fn leave_one_out_khill(&self) -> FxHashMap<String, f64> {
+        self.inner.leave_one_out_khill.clone()
+    }
+
+    /// Change in the K-Hill number when a genome is removed from the set of genomes.
+    fn delta_khill(&self, genome_id: &str) -> Option<f64> {
+        self.inner.delta_khill(genome_id)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "KHillResult(khill={}, num_genomes={})",
+            self.inner.khill(),
+            self.inner.genome_components.len()
+        )
+    }
+}
+
+/// Sketch a genomic FASTA file, or read a khill sketch file, returning a dict of k-mer hashes and counts.
+#[pyfunction]
+fn sketch_file(py: Python<'_>, path: PathBuf, params: &PySketchParams) -> Result<Hashes> {
+    py.detach(|| Ok(load_sketch(&path, &params.inner)?.hashes))
+}
+
+/// Sketch in-memory nucleotide sequences (e.g. the contigs of a genome), returning a dict of k-mer hashes and counts.
+#[pyfunction]
+fn sketch_sequences(py: Python<'_>, seqs: Vec<String>, params: &PySketchParams) -> Hashes {
+    py.detach(|| Sketch::from_seqs("in-memory sequences", &seqs, &params.inner).hashes)
+}
+
+/// Calculate the K-Hill number from a dict mapping genome identifiers to their k-mer hashes.
+#[pyfunction]
+#[pyo3(name = "khill", signature = (genome_hashes, orders = Vec::new(), threads = None))]
+fn khill_hashes(
+    py: Python<'_>,
+    genome_hashes: FxHashMap<String, Hashes>,
+    orders: Vec<f64>,
+    threads: Option<usize>,
+) -> Result<PyKHillResult> {
+    let inner = run_in_pool(py, threads, || khill_from_hashes(&genome_hashes, &orders))?;
+    Ok(PyKHillResult { inner })
+}
+
+/// Calculate the K-Hill number of genomic FASTA or khill sketch files.
+///
+/// Genomes are identified by their file names without extensions.
+#[pyfunction]
+#[pyo3(signature = (genome_files, params, orders = Vec::new(), threads = None))]
+fn khill_files(
+    py: Python<'_>,
+    genome_files: Vec<PathBuf>,
+    params: &PySketchParams,
+    orders: Vec<f64>,
+    threads: Option<usize>,
+) -> Result<PyKHillResult> {
+    let inner = run_in_pool(py, threads, || khill::khill(&genome_files, &params.inner, &orders))??;
+    Ok(PyKHillResult { inner })
+}
+
+/// Calculate the K-Hill number from in-memory sequences of each genome, given as a dict mapping
+/// genome identifiers to a list of sequences.
+#[pyfunction]
+#[pyo3(signature = (genome_seqs, params, orders = Vec::new(), threads = None))]
+fn khill_sequences(
+    py: Python<'_>,
+    genome_seqs: FxHashMap<String, Vec<String>>,
+    params: &PySketchParams,
+    orders: Vec<f64>,
+    threads: Option<usize>,
+) -> Result<PyKHillResult> {
+    let inner = run_in_pool(py, threads, || {
+        let sketches: Vec<Sketch> = genome_seqs.iter()
+            .map(|(genome_id, seqs)| Sketch::from_seqs(genome_id, seqs, &params.inner))
+            .collect();
+        khill_from_sketches(&sketches, &orders)
+    })??;
+    Ok(PyKHillResult { inner })
+}
+
+/// Run a calculation without holding the GIL, using a thread pool with the specified number of
+/// threads or the global rayon thread pool if no number of threads is specified.
+fn run_in_pool<T: Send>(py: Python<'_>, threads: Option<usize>, f: impl FnOnce() -> T + Send) -> Result<T> {
+    py.detach(|| match threads {
+        Some(threads) => Ok(ThreadPoolBuilder::new().num_threads(threads).build()?.install(f)),
+        None => Ok(f()),
+    })
+}
+
+/// Python representation of a boolean value.
+fn py_bool(value: bool) -> &'static str {
+    if value { "True" } else { "False" }
+}
+
+#[pymodule]
+#[pyo3(name = "khill")]
+fn khill_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<PySketchParams>()?;
+    m.add_class::<PyHillComponent>()?;
+    m.add_class::<PyHillNumbers>()?;
+    m.add_class::<PyKHillResult>()?;
+    m.add_function(wrap_pyfunction!(sketch_file, m)?)?;
+    m.add_function(wrap_pyfunction!(sketch_sequences, m)?)?;
+    m.add_function(wrap_pyfunction!(khill_hashes, m)?)?;
+    m.add_function(wrap_pyfunction!(khill_files, m)?)?;
+    m.add_function(wrap_pyfunction!(khill_sequences, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::IntoPyDict;
+    use tempfile::tempdir;
+
+    const GENOME1: [&str; 2] = ["ACGTACGTACGT", "GGATCCAAGT"];
+    const GENOME2: [&str; 1] = ["TTGACAGGATCCAAGTTT"];
+
+    fn sketch_params() -> PySketchParams {
+        PySketchParams::new(3, 1, true, true).unwrap()
+    }
+
+    #[test]
+    fn test_sketch_params() {
+        let params = sketch_params();
+        assert_eq!(params.__repr__(), "SketchParams(k=3, scale=1, weighted=True, skip_ambiguous=True)");
+
+        Python::initialize();
+        Python::attach(|py| {
+            for (k, scale) in [(0, 1), (65, 1), (3, 0)] {
+                let err = PySketchParams::new(k, scale, true, true).err().unwrap();
+                assert!(err.is_instance_of::<PyValueError>(py));
+            }
+        });
+    }
+
+    #[test]
+    fn test_sketch_sequences() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fasta_file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&fasta_file, format!(">seq1\n{}\n>seq2\n{}\n", GENOME1[0], GENOME1[1]))?;
+
+        Python::initialize();
+        Python::attach(|py| {
+            let params = sketch_params();
+            let hashes = sketch_sequences(py, GENOME1.map(String::from).to_vec(), &params);
+            assert_eq!(sketch_file(py, fasta_file, &params)?, hashes);
+
+            // hashes round-trip through a Python dict
+            let dict = hashes.clone().into_pyobject(py)?;
+            assert_eq!(dict.len(), hashes.len());
+            assert_eq!(dict.extract::<Hashes>()?, hashes);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_khill_interfaces() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let genome_seqs = [("genome1", GENOME1.as_slice()), ("genome2", GENOME2.as_slice())];
+        let mut genome_files = Vec::new();
+        for (genome_id, seqs) in genome_seqs {
+            let fasta_file = temp_dir.path().join(format!("{genome_id}.fna"));
+            let records: String = seqs.iter().enumerate().map(|(i, seq)| format!(">seq{i}\n{seq}\n")).collect();
+            std::fs::write(&fasta_file, records)?;
+            genome_files.push(fasta_file);
+        }
+
+        Python::initialize();
+        Python::attach(|py| {
+            let params = sketch_params();
+            let orders = vec![0.0, 1.0, f64::INFINITY];
+
+            // arguments are converted from Python objects as they would be when called from Python
+            let seqs_dict = genome_seqs.map(|(genome_id, seqs)| (genome_id, seqs.to_vec())).into_py_dict(py)?;
+            let hashes_dict = genome_seqs
+                .map(|(genome_id, seqs)| (genome_id, sketch_sequences(py, seqs.iter().map(|s| s.to_string()).collect(), &params)))
+                .into_py_dict(py)?;
+
+            let from_files = khill_files(py, genome_files, &params, orders.clone(), None)?;
+            let from_hashes = khill_hashes(py, hashes_dict.extract()?, orders.clone(), Some(2))?;
+            let from_seqs = khill_sequences(py, seqs_dict.extract()?, &params, orders, None)?;
+
+            assert!(from_files.khill() > 1.0 && from_files.khill() < 2.0);
+            for result in [&from_hashes, &from_seqs] {
+                assert!((result.khill() - from_files.khill()).abs() < 1e-12);
+                assert!((result.alpha_diversity() - from_files.alpha_diversity()).abs() < 1e-12);
+                assert!((result.gamma_diversity() - from_files.gamma_diversity()).abs() < 1e-12);
+                assert_eq!(result.hill_numbers().len(), 3);
+                assert_eq!(result.leave_one_out_khill(), from_files.leave_one_out_khill());
+
+                let components = result.genome_components();
+                for (genome_id, component) in from_files.genome_components() {
+                    assert!((components[&genome_id].beta_entropy() - component.beta_entropy()).abs() < 1e-12);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_hill_component_to_dict() -> PyResult<()> {
+        let component = PyHillComponent {
+            entropy: 2.0,
+            kl_divergence: 0.5,
+            weight: 0.25,
+        };
+
+        Python::initialize();
+        Python::attach(|py| {
+            let dict = component.to_dict(py)?;
+            assert_eq!(dict.len(), 4);
+            for (key, value) in [("entropy", 2.0), ("kl_divergence", 0.5), ("weight", 0.25), ("beta_entropy", 0.125)] {
+                assert_eq!(dict.get_item(key)?.unwrap().extract::<f64>()?, value);
+            }
+
+            Ok(())
+        })
+    }
+}
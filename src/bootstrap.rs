@@ -0,0 +1,112 @@
+//! This module provides bootstrap estimates of uncertainty in the K-Hill number of a group of genomes.
+//!
+//! Genomes within a group are resampled with replacement and the K-Hill number recalculated for
+//! each bootstrap replicate using the previously calculated genome hashes. Replicates are summarized
+//! by their mean, standard error, and percentile confidence interval. Each replicate is assigned its
+//! own seed derived from a user-specified seed so results are reproducible regardless of the number
+//! of threads used.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::hashing::Hashes;
+use crate::khill::khill_number;
+
+/// Summary of bootstrap replicates of the K-Hill number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BootstrapSummary {
+    pub replicates: usize,
+    pub mean: f64,
+    pub std_err: f64,
+    pub ci_lower: f64,
+    pub ci_upper: f64,
+}
+
+/// Calculate K-Hill number for bootstrap replicates of genomes resampled with replacement.
+pub fn bootstrap_khill(genome_hashes: &[&Hashes], replicates: usize, seed: u64) -> Vec<f64> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let replicate_seeds: Vec<u64> = (0..replicates).map(|_| rng.r#gen()).collect();
+
+    replicate_seeds
+        .par_iter()
+        .map(|&replicate_seed| {
+            let mut rng = StdRng::seed_from_u64(replicate_seed);
+            let resampled: Vec<&Hashes> = (0..genome_hashes.len())
+                .map(|_| genome_hashes[rng.gen_range(0..genome_hashes.len())])
+                .collect();
+
+            khill_number(&resampled)
+        })
+        .collect()
+}
+
+/// Summarize bootstrap replicates with a percentile confidence interval at the specified confidence level.
+pub fn summarize_replicates(values: &[f64], confidence: f64) -> BootstrapSummary {
+    let n = values.len();
+    let mean = values.iter().sum::<f64>() / n as f64;
+
+    // standard error is the standard deviation of the bootstrap replicates
+    let std_err = if n > 1 {
+        let sum_sq = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>();
+        (sum_sq / (n - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let alpha = 1.0 - confidence;
+
+    BootstrapSummary {
+        replicates: n,
+        mean,
+        std_err,
+        ci_lower: quantile(&sorted, alpha / 2.0),
+        ci_upper: quantile(&sorted, 1.0 - alpha / 2.0),
+    }
+}
+
+/// Calculate quantile of sorted values using linear interpolation between closest ranks.
+fn quantile(sorted: &[f64], p: f64) -> f64 {
+    let pos = p * (sorted.len() - 1) as f64;
+    let lower = pos.floor() as usize;
+    let upper = pos.ceil() as usize;
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::tests::hashes;
+
+    #[test]
+    fn test_bootstrap_is_reproducible() {
+        let genome1 = hashes(&[(1, 2), (2, 1), (3, 1)]);
+        let genome2 = hashes(&[(1, 1), (4, 3)]);
+        let genome3 = hashes(&[(2, 2), (5, 1), (6, 1)]);
+        let genomes = vec![&genome1, &genome2, &genome3];
+
+        let values = bootstrap_khill(&genomes, 50, 42);
+        assert_eq!(values.len(), 50);
+        assert_eq!(values, bootstrap_khill(&genomes, 50, 42));
+
+        // K-Hill of resampled genomes can't exceed the number of genomes
+        for v in &values {
+            assert!(*v >= 1.0 - 1e-12 && *v <= 3.0 + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_summarize_replicates() {
+        let values = [5.0, 1.0, 4.0, 2.0, 3.0];
+        let summary = summarize_replicates(&values, 0.5);
+
+        assert_eq!(summary.replicates, 5);
+        assert_eq!(summary.mean, 3.0);
+        assert!((summary.std_err - 2.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(summary.ci_lower, 2.0);
+        assert_eq!(summary.ci_upper, 4.0);
+    }
+}
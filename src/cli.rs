@@ -3,27 +3,209 @@
 //!
 //! This file defines the `Cli` struct using the `clap` crate to parse and validate command-line arguments.
 //! It includes options for specifying input directories or genome group tables, output directory, k-mer length,
-//! sketch scaling factor, and number of threads. Custom value parsers are provided for k-mer length and thread count.
+//! sketch scaling factor, and number of threads. The `sketch` subcommand writes sketches of genomes to disk
+//! so they can be reused by later runs, and the `dist` subcommand reports pairwise similarity between
+//! genomes in each group. The `compare` subcommand tests for differences in K-Hill between pairs of groups,
+//! and the `hierarchy` subcommand partitions diversity across nested groups. Custom value parsers are
+//! provided for sketch parameters, Hill number orders, confidence levels, fractions, counts, and thread count.
 //! The CLI output is styled using the `anstyle` crate for improved readability.
 
 use std::path::PathBuf;
 
-use clap::Parser;
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use khill::sketch_params::{SketchParams, MAX_KMER_LENGTH, MIN_KMER_LENGTH};
+use khill::{group_analysis, output};
 
 const DEFAULT_K: u8 = 19;
 const DEFAULT_SCALE: u64 = 100;
+const DEFAULT_CONFIDENCE: f64 = 0.95;
+const DEFAULT_SEED: u64 = 42;
+const DEFAULT_RAREFACTION_REPLICATES: usize = 10;
+const DEFAULT_PERMUTATIONS: usize = 999;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(styles=get_styles())]
 #[command(disable_help_subcommand = true)]
 #[command(arg_required_else_help = true)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Directory of genomes to process
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub khill: Option<KhillArgs>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create sketches of genomes for use in subsequent runs
+    Sketch(SketchArgs),
+
+    /// Calculate pairwise Jaccard, containment, and ANI between genomes in each group
+    Dist(DistArgs),
+
+    /// Test for differences in K-Hill between pairs of groups using a permutation test
+    Compare(CompareArgs),
+
+    /// Partition diversity across a hierarchy of nested groups
+    Hierarchy(HierarchyArgs),
+}
+
+/// Policy for handling genomes that fail to be sketched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Exclude failed genomes and list them in failed_genomes.tsv
+    Skip,
+    /// Stop after reporting all genomes that failed
+    Fail,
+}
+
+/// Layout of pairwise genome similarity output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DistLayout {
+    /// Square matrix for each group and similarity measure
+    Full,
+    /// Lower-triangular matrix for each group and similarity measure
+    LowerTriangular,
+    /// Single table listing pairs of genomes with all similarity measures
+    Sparse,
+}
+
+/// Order of groups and genomes in output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// Order in which groups and genomes were specified
+    Input,
+    /// Sorted by group and genome identifiers
+    Id,
+    /// Sorted by decreasing K-Hill of groups and beta entropy of genomes
+    Value,
+}
+
+impl From<SortOrder> for group_analysis::SortOrder {
+    fn from(sort_order: SortOrder) -> Self {
+        match sort_order {
+            SortOrder::Input => group_analysis::SortOrder::Input,
+            SortOrder::Id => group_analysis::SortOrder::Id,
+            SortOrder::Value => group_analysis::SortOrder::Value,
+        }
+    }
+}
+
+/// Format of group and per-genome result files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab-separated values
+    Tsv,
+    /// JSON document with run parameters
+    Json,
+    /// Parquet tables with run parameters as key-value metadata
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl From<OutputFormat> for output::OutputFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Tsv => output::OutputFormat::Tsv,
+            OutputFormat::Json => output::OutputFormat::Json,
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => output::OutputFormat::Parquet,
+        }
+    }
+}
+
+/// Arguments for calculating K-Hill across groups of genomes.
+///
+/// clap leaves the group of a struct with flattened arguments empty, so the input and output
+/// arguments are explicitly placed in the `khill` group used to detect that these arguments are present.
+#[derive(Args)]
+#[group(id = "khill")]
+pub struct KhillArgs {
+    /// Directory of genomes or sketches to process
+    #[arg(short = 'i', long, help_heading = "Inputs", groups = ["input", "khill"], value_parser = clap::value_parser!(PathBuf))]
+    pub input_dir: Option<PathBuf>,
+
+    /// TSV file indicating groups of genomes to process (group_id, path to FASTA or sketch file)
+    #[arg(short = 'g', long, help_heading = "Inputs", groups = ["input", "khill"], value_parser = clap::value_parser!(PathBuf))]
+    pub genome_group_table: Option<PathBuf>,
+
+    /// Output directory
+    #[arg(short = 'o', long, help_heading = "Output", group = "khill", value_parser = clap::value_parser!(PathBuf))]
+    pub out_dir: PathBuf,
+
+    #[command(flatten)]
+    pub sketching: SketchingArgs,
+
+    /// Orders of alpha, gamma, and beta Hill numbers to report (e.g. 0,0.5,1,2,inf)
+    #[arg(long, help_heading = "Output", value_delimiter = ',', value_parser = validate_hill_order)]
+    pub hill_orders: Vec<f64>,
+
+    /// Number of bootstrap replicates used to estimate uncertainty in K-Hill (0 to disable)
+    #[arg(long, help_heading = "Bootstrap", default_value_t = 0)]
+    pub bootstrap: usize,
+
+    /// Confidence level of bootstrap percentile confidence intervals
+    #[arg(long, help_heading = "Bootstrap", default_value_t = DEFAULT_CONFIDENCE, value_parser = validate_confidence)]
+    pub confidence: f64,
+
+    /// Step size of genome subsets used to calculate K-Hill rarefaction curves (0 to disable)
+    #[arg(long, help_heading = "Rarefaction", default_value_t = 0)]
+    pub rarefaction_step: usize,
+
+    /// Number of random subsets of genomes to sample at each rarefaction step
+    #[arg(long, help_heading = "Rarefaction", default_value_t = DEFAULT_RAREFACTION_REPLICATES, value_parser = validate_count)]
+    pub rarefaction_replicates: usize,
+
+    /// Seed for random number generator
+    #[arg(long, default_value_t = DEFAULT_SEED)]
+    pub seed: u64,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+
+    /// Order of groups and genomes in output files
+    #[arg(long, value_enum, default_value_t = SortOrder::Input)]
+    pub sort_by: SortOrder,
+
+    /// Format of group and per-genome result files
+    #[arg(long, help_heading = "Output", value_enum, default_value_t = OutputFormat::Tsv)]
+    pub output_format: OutputFormat,
+
+    /// SQLite database to append results of this run to (requires the database feature)
+    #[arg(long, help_heading = "Output", value_parser = clap::value_parser!(PathBuf))]
+    pub database: Option<PathBuf>,
+}
+
+/// Arguments for sketching genomes.
+#[derive(Args)]
+pub struct SketchArgs {
+    /// Directory of genomes to sketch
+    #[arg(short = 'i', long, help_heading = "Inputs", group= "input", value_parser = clap::value_parser!(PathBuf))]
+    pub input_dir: Option<PathBuf>,
+
+    /// TSV file indicating groups of genomes to sketch (group_id, path to FASTA file)
+    #[arg(short = 'g', long, help_heading = "Inputs", group = "input", value_parser = clap::value_parser!(PathBuf))]
+    pub genome_group_table: Option<PathBuf>,
+
+    /// Output directory for sketch files
+    #[arg(short = 'o', long, help_heading = "Output", value_parser = clap::value_parser!(PathBuf))]
+    pub out_dir: PathBuf,
+
+    #[command(flatten)]
+    pub sketching: SketchingArgs,
+}
+
+/// Arguments for calculating pairwise similarity between genomes.
+#[derive(Args)]
+pub struct DistArgs {
+    /// Directory of genomes or sketches to process
     #[arg(short = 'i', long, help_heading = "Inputs", group= "input", value_parser = clap::value_parser!(PathBuf))]
     pub input_dir: Option<PathBuf>,
 
-    /// TSV file indicating groups of genomes to process (group_id, path to FASTA file)
+    /// TSV file indicating groups of genomes to process (group_id, path to FASTA or sketch file)
     #[arg(short = 'g', long, help_heading = "Inputs", group = "input", value_parser = clap::value_parser!(PathBuf))]
     pub genome_group_table: Option<PathBuf>,
 
@@ -31,18 +213,115 @@ pub struct Cli {
     #[arg(short = 'o', long, help_heading = "Output", value_parser = clap::value_parser!(PathBuf))]
     pub out_dir: PathBuf,
 
-    /// Length of k-mers to use
-    #[arg(short, long, help_heading = "Sketching parameters", default_value_t = DEFAULT_K, value_parser = validate_kmer_length)]
+    /// Layout of pairwise similarity output
+    #[arg(long, help_heading = "Output", value_enum, default_value_t = DistLayout::Full)]
+    pub layout: DistLayout,
+
+    /// Minimum ANI of pairs of genomes reported in the sparse layout
+    #[arg(long, help_heading = "Output", default_value_t = 0.0, value_parser = validate_fraction)]
+    pub min_ani: f64,
+
+    #[command(flatten)]
+    pub sketching: SketchingArgs,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+}
+
+/// Arguments for comparing K-Hill between pairs of groups.
+#[derive(Args)]
+pub struct CompareArgs {
+    /// TSV file indicating groups of genomes to process (group_id, path to FASTA or sketch file)
+    #[arg(short = 'g', long, help_heading = "Inputs", value_parser = clap::value_parser!(PathBuf))]
+    pub genome_group_table: PathBuf,
+
+    /// TSV file indicating pairs of groups to compare (group_id_a, group_id_b) [default: all pairs]
+    #[arg(short = 'p', long, help_heading = "Inputs", value_parser = clap::value_parser!(PathBuf))]
+    pub group_pairs: Option<PathBuf>,
+
+    /// Output directory
+    #[arg(short = 'o', long, help_heading = "Output", value_parser = clap::value_parser!(PathBuf))]
+    pub out_dir: PathBuf,
+
+    #[command(flatten)]
+    pub sketching: SketchingArgs,
+
+    /// Number of permutations of group labels used to calculate p-values
+    #[arg(long, help_heading = "Permutation test", default_value_t = DEFAULT_PERMUTATIONS, value_parser = validate_count)]
+    pub permutations: usize,
+
+    /// Seed for random number generator
+    #[arg(long, help_heading = "Permutation test", default_value_t = DEFAULT_SEED)]
+    pub seed: u64,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+}
+
+/// Arguments for partitioning diversity across nested groups.
+#[derive(Args)]
+pub struct HierarchyArgs {
+    /// TSV file with a header indicating groups of genomes to process (group_id, genome_file, and nested levels)
+    #[arg(short = 'g', long, help_heading = "Inputs", value_parser = clap::value_parser!(PathBuf))]
+    pub genome_group_table: PathBuf,
+
+    /// Columns of the genome group table giving nested groups within each group, from highest to lowest level
+    #[arg(short = 'l', long, help_heading = "Inputs", value_delimiter = ',')]
+    pub levels: Vec<String>,
+
+    /// Output directory
+    #[arg(short = 'o', long, help_heading = "Output", value_parser = clap::value_parser!(PathBuf))]
+    pub out_dir: PathBuf,
+
+    #[command(flatten)]
+    pub sketching: SketchingArgs,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+}
+
+/// Arguments controlling how genomes are sketched, shared by all commands.
+#[derive(Args)]
+pub struct SketchingArgs {
+    #[arg(short, long, help_heading = "Sketching parameters", default_value_t = DEFAULT_K, value_parser = validate_kmer_length,
+        help = format!("Length of k-mers to use, in the range [{MIN_KMER_LENGTH}, {MAX_KMER_LENGTH}]"))]
     pub kmer_length: u8,
 
     /// Sketch scaling factor (e.g. 100 will examine ~1% of k-mers)
-    #[arg(short = 's', long, help_heading = "Sketching parameters", default_value_t = DEFAULT_SCALE)]
+    #[arg(short = 's', long, help_heading = "Sketching parameters", default_value_t = DEFAULT_SCALE, value_parser = validate_scale)]
     pub scale: u64,
 
+    /// Treat ambiguous bases as an A instead of skipping k-mers containing ambiguous bases
+    #[arg(long, help_heading = "Sketching parameters", default_value_t = false)]
+    pub ambiguous_as_a: bool,
+
+    /// Only consider the presence of k-mers in each genome instead of weighting k-mers by their number of occurrences
+    #[arg(long, help_heading = "Sketching parameters", default_value_t = false)]
+    pub unweighted: bool,
+
     /// Number of threads to use
     #[arg(short, long, default_value_t = 1, value_parser = validate_threads)]
     pub threads: usize,
 
+    /// Policy for genomes that fail to be sketched
+    #[arg(long, value_enum, default_value_t = OnError::Fail)]
+    pub on_error: OnError,
+}
+
+impl SketchingArgs {
+    /// Sketch parameters specified by the arguments.
+    pub fn sketch_params(&self) -> Result<SketchParams> {
+        SketchParams::try_new(self.kmer_length, self.scale, !self.unweighted, !self.ambiguous_as_a)
+    }
+}
+
+/// Arguments for commands which hold sketches of genome or sketch files in a cache.
+#[derive(Args)]
+pub struct CacheArgs {
+    /// Maximum memory (in MB) used to hold sketches in memory before spilling them to disk [default: unlimited]
+    #[arg(long)]
+    pub max_cache_memory: Option<u64>,
+
     /// Skip verification that genomic FASTA files exist
     #[arg(long, default_value_t = false)]
     pub skip_file_check: bool,
@@ -53,13 +332,69 @@ fn validate_kmer_length(k: &str) -> Result<u8, String> {
         .parse()
         .map_err(|_| format!("`{k}` isn't a valid k-mer length"))?;
 
-    if !(1..=32).contains(&k) {
-        return Err("k-mer length must be in the range [1, 32]".to_string());
-    }
+    SketchParams::check_kmer_length(k).map_err(|err| err.to_string())?;
 
     Ok(k)
 }
 
+fn validate_scale(scale: &str) -> Result<u64, String> {
+    let scale: u64 = scale
+        .parse()
+        .map_err(|_| format!("`{scale}` isn't a valid scale"))?;
+
+    SketchParams::check_scale(scale).map_err(|err| err.to_string())?;
+
+    Ok(scale)
+}
+
+fn validate_hill_order(q: &str) -> Result<f64, String> {
+    let q: f64 = q
+        .parse()
+        .map_err(|_| format!("`{q}` isn't a valid Hill number order"))?;
+
+    if q.is_nan() || q < 0.0 {
+        return Err("Hill number orders must be non-negative".to_string());
+    }
+
+    Ok(q)
+}
+
+fn validate_confidence(confidence: &str) -> Result<f64, String> {
+    let confidence: f64 = confidence
+        .parse()
+        .map_err(|_| format!("`{confidence}` isn't a valid confidence level"))?;
+
+    if !(confidence > 0.0 && confidence < 1.0) {
+        return Err("Confidence level must be in the range (0, 1)".to_string());
+    }
+
+    Ok(confidence)
+}
+
+fn validate_fraction(value: &str) -> Result<f64, String> {
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("`{value}` isn't a valid value"))?;
+
+    if !(0.0..=1.0).contains(&value) {
+        return Err("Value must be in the range [0, 1]".to_string());
+    }
+
+    Ok(value)
+}
+
+fn validate_count(count: &str) -> Result<usize, String> {
+    let count: usize = count
+        .parse()
+        .map_err(|_| format!("`{count}` isn't a valid value"))?;
+
+    if count == 0 {
+        return Err("Value must be at least 1".to_string());
+    }
+
+    Ok(count)
+}
+
 fn validate_threads(threads: &str) -> Result<usize, String> {
     let threads: usize = threads
         .parse()
@@ -113,3 +448,42 @@ fn test_verify_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
+
+#[test]
+fn test_parse_khill_args() {
+    let cli = Cli::try_parse_from(["khill", "-i", "genomes", "-o", "out"]).unwrap();
+    assert!(cli.command.is_none());
+    let khill_args = cli.khill.expect("K-Hill arguments without a subcommand");
+    assert_eq!(khill_args.input_dir, Some(PathBuf::from("genomes")));
+    assert_eq!(khill_args.out_dir, PathBuf::from("out"));
+    assert!(khill_args.database.is_none());
+
+    let cli = Cli::try_parse_from(["khill", "-g", "groups.tsv", "-o", "out", "--database", "results.db"]).unwrap();
+    assert_eq!(cli.khill.unwrap().database, Some(PathBuf::from("results.db")));
+
+    let cli = Cli::try_parse_from(["khill", "sketch", "-i", "genomes", "-o", "out"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Sketch(_))));
+
+    assert!(Cli::try_parse_from(["khill", "-i", "genomes", "-o", "out", "--rarefaction-replicates", "0"]).is_err());
+    let cli = Cli::try_parse_from(["khill", "-i", "genomes", "-o", "out", "--rarefaction-replicates", "1"]).unwrap();
+    assert_eq!(cli.khill.unwrap().rarefaction_replicates, 1);
+
+    assert!(Cli::try_parse_from(["khill", "compare", "-g", "groups.tsv", "-o", "out", "--permutations", "0"]).is_err());
+    let cli = Cli::try_parse_from(["khill", "compare", "-g", "groups.tsv", "-o", "out", "--permutations", "1"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Compare(CompareArgs { permutations: 1, .. }))));
+}
+
+#[test]
+fn test_parse_sketch_params() {
+    for k in ["0", "65"] {
+        assert!(Cli::try_parse_from(["khill", "sketch", "-i", "genomes", "-o", "out", "-k", k]).is_err());
+    }
+    assert!(Cli::try_parse_from(["khill", "sketch", "-i", "genomes", "-o", "out", "-s", "0"]).is_err());
+
+    let cli = Cli::try_parse_from(["khill", "sketch", "-i", "genomes", "-o", "out", "-k", "64", "-s", "1"]).unwrap();
+    let Some(Command::Sketch(sketch_args)) = cli.command else {
+        panic!("expected sketch command");
+    };
+    let sketch_params = sketch_args.sketching.sketch_params().unwrap();
+    assert_eq!((sketch_params.k(), sketch_params.scale()), (64, 1));
+}
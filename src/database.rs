@@ -0,0 +1,282 @@
+//! This module stores K-Hill results in a SQLite database so results of many runs can be queried together.
+//!
+//! Each run is appended to the database with a new run identifier, allowing results from runs with
+//! different parameters (e.g. a sweep over k-mer lengths and scales) to be compared with SQL queries.
+//! The database contains the following tables, which are created if they do not already exist:
+//!
+//! - `runs`: time, version, and command line of each run
+//! - `parameters`: sketching and analysis parameters of each run
+//! - `groups`: K-Hill and related statistics for each group of genomes
+//! - `hill_numbers`: alpha, gamma, and beta Hill numbers of each requested order for each group
+//! - `genomes`: genomes in each group along with any metadata from the genome group table
+//! - `genome_components`: contribution of each genome to the K-Hill number of its group
+//!
+//! All results of a run are written in a single transaction so a failed run never leaves partial results.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, Transaction};
+use serde_json::{Map, Value};
+
+use crate::output::Results;
+
+/// Version of the database schema, stored as the SQLite user version.
+const SCHEMA_VERSION: i64 = 1;
+
+/// Statements creating the tables of the database.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    run_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    version TEXT NOT NULL,
+    command_line TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS parameters (
+    run_id INTEGER PRIMARY KEY REFERENCES runs(run_id),
+    kmer_length INTEGER NOT NULL,
+    scale INTEGER NOT NULL,
+    kmer_weighting TEXT NOT NULL,
+    skip_ambiguous INTEGER NOT NULL,
+    hill_orders TEXT NOT NULL,
+    bootstrap_replicates INTEGER NOT NULL,
+    confidence REAL NOT NULL,
+    rarefaction_step INTEGER NOT NULL,
+    rarefaction_replicates INTEGER NOT NULL,
+    seed INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS groups (
+    run_id INTEGER NOT NULL REFERENCES runs(run_id),
+    group_id TEXT NOT NULL,
+    num_genomes INTEGER NOT NULL,
+    khill REAL,
+    alpha_entropy REAL,
+    gamma_entropy REAL,
+    beta_entropy REAL,
+    alpha_diversity REAL,
+    gamma_diversity REAL,
+    PRIMARY KEY (run_id, group_id)
+);
+
+CREATE TABLE IF NOT EXISTS hill_numbers (
+    run_id INTEGER NOT NULL,
+    group_id TEXT NOT NULL,
+    q REAL NOT NULL,
+    alpha REAL,
+    gamma REAL,
+    beta REAL,
+    PRIMARY KEY (run_id, group_id, q),
+    FOREIGN KEY (run_id, group_id) REFERENCES groups(run_id, group_id)
+);
+
+CREATE TABLE IF NOT EXISTS genomes (
+    run_id INTEGER NOT NULL,
+    group_id TEXT NOT NULL,
+    genome_id TEXT NOT NULL,
+    metadata TEXT NOT NULL,
+    PRIMARY KEY (run_id, group_id, genome_id),
+    FOREIGN KEY (run_id, group_id) REFERENCES groups(run_id, group_id)
+);
+
+CREATE TABLE IF NOT EXISTS genome_components (
+    run_id INTEGER NOT NULL,
+    group_id TEXT NOT NULL,
+    genome_id TEXT NOT NULL,
+    beta_entropy REAL,
+    kl_divergence REAL,
+    weight REAL,
+    entropy REAL,
+    khill_without_genome REAL,
+    delta_khill REAL,
+    PRIMARY KEY (run_id, group_id, genome_id),
+    FOREIGN KEY (run_id, group_id, genome_id) REFERENCES genomes(run_id, group_id, genome_id)
+);
+";
+
+/// Append results of a run to a SQLite database, creating the database if it does not exist.
+///
+/// Returns the identifier assigned to the run.
+pub fn write_results(db_path: &Path, results: &Results, command_line: &str) -> Result<i64> {
+    let mut conn = Connection::open(db_path)
+        .context(format!("Failed to open database {}", db_path.display()))?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    init_schema(&conn).context(format!("Failed to initialize database {}", db_path.display()))?;
+
+    let tx = conn.transaction()?;
+    let run_id = insert_results(&tx, results, command_line)
+        .context(format!("Failed to write results to database {}", db_path.display()))?;
+    tx.commit()?;
+
+    Ok(run_id)
+}
+
+/// Create tables if required, verifying that an existing database uses the current schema.
+fn init_schema(conn: &Connection) -> Result<()> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    match version {
+        0 => {
+            conn.execute_batch(SCHEMA)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        SCHEMA_VERSION => {}
+        _ => bail!("Database has schema version {}, but version {} is required", version, SCHEMA_VERSION),
+    }
+
+    Ok(())
+}
+
+/// Insert a run and all of its results.
+fn insert_results(tx: &Transaction, results: &Results, command_line: &str) -> Result<i64> {
+    let parameters = &results.parameters;
+    tx.execute(
+        "INSERT INTO runs (version, command_line) VALUES (?1, ?2)",
+        params![parameters.version, command_line],
+    )?;
+    let run_id = tx.last_insert_rowid();
+
+    let hill_orders = serde_json::to_value(parameters)?["hill_orders"].to_string();
+    let scale = i64::try_from(parameters.scale).context("Scale is too large to store in the database")?;
+    tx.execute(
+        "INSERT INTO parameters (run_id, kmer_length, scale, kmer_weighting, skip_ambiguous, hill_orders,
+            bootstrap_replicates, confidence, rarefaction_step, rarefaction_replicates, seed)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            run_id,
+            parameters.kmer_length,
+            scale,
+            parameters.kmer_weighting,
+            parameters.skip_ambiguous,
+            hill_orders,
+            parameters.bootstrap_replicates as i64,
+            parameters.confidence,
+            parameters.rarefaction_step as i64,
+            parameters.rarefaction_replicates as i64,
+            // SQLite integers are signed, so seeds are stored with the same bits as a signed integer
+            parameters.seed as i64,
+        ],
+    )?;
+
+    let mut insert_group = tx.prepare(
+        "INSERT INTO groups (run_id, group_id, num_genomes, khill, alpha_entropy, gamma_entropy, beta_entropy,
+            alpha_diversity, gamma_diversity)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    let mut insert_hill_numbers = tx.prepare(
+        "INSERT INTO hill_numbers (run_id, group_id, q, alpha, gamma, beta) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    let mut insert_genome = tx.prepare(
+        "INSERT INTO genomes (run_id, group_id, genome_id, metadata) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    let mut insert_component = tx.prepare(
+        "INSERT INTO genome_components (run_id, group_id, genome_id, beta_entropy, kl_divergence, weight, entropy,
+            khill_without_genome, delta_khill)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+
+    for group_results in &results.groups {
+        let group = &group_results.group;
+        insert_group.execute(params![
+            run_id,
+            group.group_id,
+            group.num_genomes as i64,
+            group.khill,
+            group.alpha_entropy,
+            group.gamma_entropy,
+            group.beta_entropy,
+            group.alpha_diversity,
+            group.gamma_diversity,
+        ])?;
+
+        for hill in &group.hill_numbers {
+            insert_hill_numbers.execute(params![run_id, group.group_id, hill.q, hill.alpha, hill.gamma, hill.beta])?;
+        }
+
+        for genome in &group_results.genomes {
+            let metadata: Map<String, Value> = results.metadata_columns.iter().cloned()
+                .zip(genome.metadata.iter().map(|value| value.clone().map_or(Value::Null, Value::String)))
+                .collect();
+            insert_genome.execute(params![run_id, genome.group_id, genome.genome_id, Value::Object(metadata).to_string()])?;
+            insert_component.execute(params![
+                run_id,
+                genome.group_id,
+                genome.genome_id,
+                genome.beta_entropy,
+                genome.kl_divergence,
+                genome.weight,
+                genome.entropy,
+                genome.khill_without_genome,
+                genome.delta_khill,
+            ])?;
+        }
+    }
+
+    Ok(run_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::tests::results;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_results() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("khill.sqlite");
+
+        // runs are appended to the database with increasing identifiers
+        let results = results();
+        assert_eq!(write_results(&db_path, &results, "khill -g groups.tsv -o run1")?, 1);
+        assert_eq!(write_results(&db_path, &results, "khill -g groups.tsv -o run2")?, 2);
+
+        let conn = Connection::open(&db_path)?;
+        let count = |table: &str| -> Result<i64> {
+            Ok(conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0))?)
+        };
+        assert_eq!(count("runs")?, 2);
+        assert_eq!(count("parameters")?, 2);
+        assert_eq!(count("groups")?, 4);
+        assert_eq!(count("hill_numbers")?, 8);
+        assert_eq!(count("genomes")?, 6);
+        assert_eq!(count("genome_components")?, 6);
+
+        let (kmer_length, hill_orders): (u8, String) = conn.query_row(
+            "SELECT kmer_length, hill_orders FROM parameters WHERE run_id = 2",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!((kmer_length, hill_orders.as_str()), (19, "[0.0,\"inf\"]"));
+
+        let khill: f64 = conn.query_row(
+            "SELECT khill FROM groups WHERE run_id = 1 AND group_id = 'group2'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(khill, 2.0);
+
+        let metadata: String = conn.query_row(
+            "SELECT metadata FROM genomes WHERE run_id = 1 AND genome_id = 'genome2'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(metadata, "{\"habitat\":null}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_version() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("khill.sqlite");
+
+        let conn = Connection::open(&db_path)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+        drop(conn);
+
+        assert!(write_results(&db_path, &results(), "khill").is_err());
+
+        Ok(())
+    }
+}
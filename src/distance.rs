@@ -0,0 +1,155 @@
+//! This module estimates pairwise similarity between genomes from their FracMinHash sketches.
+//!
+//! Since FracMinHash sketches retain all k-mers with a hash below a fixed threshold, the sketches of
+//! two genomes can be compared directly to estimate the Jaccard index and containment of their k-mer
+//! sets. The weighted Jaccard index also accounts for the number of times each k-mer occurs. Average
+//! nucleotide identity (ANI) is estimated from the Jaccard index using the Mash distance
+//! ([Ondov et al., 2016](https://doi.org/10.1186/s13059-016-0997-x)).
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::hashing::{count_u64, Hashes};
+
+/// Similarity between a pair of genomes, A and B.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairwiseDistance {
+    /// Jaccard index of k-mers in A and B
+    pub jaccard: f64,
+    /// Jaccard index weighted by the number of occurrences of each k-mer
+    pub weighted_jaccard: f64,
+    /// Fraction of k-mers in A also found in B
+    pub containment_a: f64,
+    /// Fraction of k-mers in B also found in A
+    pub containment_b: f64,
+    /// Average nucleotide identity estimated from the Mash distance
+    pub ani: f64,
+}
+
+impl PairwiseDistance {
+    /// Containment of the first genome in the second genome of a pair, or the reverse if `reverse` is true.
+    pub fn containment(&self, reverse: bool) -> f64 {
+        if reverse { self.containment_b } else { self.containment_a }
+    }
+}
+
+/// Value of a similarity measure for a pair of genomes, with the pair reversed if the flag is true.
+pub type SimilarityMeasure = fn(&PairwiseDistance, bool) -> f64;
+
+/// Names of similarity measures and functions to obtain their values.
+pub const SIMILARITY_MEASURES: [(&str, SimilarityMeasure); 4] = [
+    ("jaccard", |dist, _| dist.jaccard),
+    ("weighted_jaccard", |dist, _| dist.weighted_jaccard),
+    ("containment", PairwiseDistance::containment),
+    ("ani", |dist, _| dist.ani),
+];
+
+/// Calculate similarity between two genomes from their k-mer hashes.
+pub fn pairwise_distance(hashes_a: &Hashes, hashes_b: &Hashes, kmer_length: u8) -> PairwiseDistance {
+    // iterate over the smaller set of hashes to find shared k-mers
+    let (smaller, larger) = if hashes_a.len() <= hashes_b.len() { (hashes_a, hashes_b) } else { (hashes_b, hashes_a) };
+
+    let mut shared = 0;
+    let mut sum_min_counts = 0u64;
+    for (hash, &count) in smaller {
+        if let Some(&other_count) = larger.get(hash) {
+            shared += 1;
+            sum_min_counts += count_u64(count.min(other_count));
+        }
+    }
+
+    let total_counts = |hashes: &Hashes| hashes.values().map(|&c| count_u64(c)).sum::<u64>();
+    let sum_max_counts = total_counts(hashes_a) + total_counts(hashes_b) - sum_min_counts;
+
+    let union = hashes_a.len() + hashes_b.len() - shared;
+    let jaccard = ratio(shared as u64, union as u64);
+
+    PairwiseDistance {
+        jaccard,
+        weighted_jaccard: ratio(sum_min_counts, sum_max_counts),
+        containment_a: ratio(shared as u64, hashes_a.len() as u64),
+        containment_b: ratio(shared as u64, hashes_b.len() as u64),
+        ani: mash_ani(jaccard, kmer_length),
+    }
+}
+
+/// Estimate average nucleotide identity from the Jaccard index using the Mash distance.
+pub fn mash_ani(jaccard: f64, kmer_length: u8) -> f64 {
+    if jaccard <= 0.0 {
+        return 0.0;
+    }
+
+    let mash_distance = -(2.0 * jaccard / (1.0 + jaccard)).ln() / kmer_length as f64;
+    (1.0 - mash_distance).max(0.0)
+}
+
+/// Calculate similarity between all pairs of genomes in parallel.
+///
+/// Pairs are returned as (i, j, distance) with i > j, ordered by row of the lower-triangular matrix.
+pub fn pairwise_distances(genome_hashes: &[&Hashes], kmer_length: u8) -> Vec<(usize, usize, PairwiseDistance)> {
+    let pairs: Vec<(usize, usize)> = (1..genome_hashes.len())
+        .flat_map(|i| (0..i).map(move |j| (i, j)))
+        .collect();
+
+    pairs
+        .par_iter()
+        .map(|&(i, j)| (i, j, pairwise_distance(genome_hashes[i], genome_hashes[j], kmer_length)))
+        .collect()
+}
+
+/// Ratio of two values, defined as 0 if the denominator is 0.
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::tests::hashes;
+
+    #[test]
+    fn test_pairwise_distance() {
+        let genome1 = hashes(&[(1, 2), (2, 1), (3, 1), (4, 1)]);
+        let genome2 = hashes(&[(1, 1), (2, 3)]);
+
+        let dist = pairwise_distance(&genome1, &genome2, 21);
+        assert_eq!(dist.jaccard, 0.5);
+        assert_eq!(dist.weighted_jaccard, 2.0 / 7.0);
+        assert_eq!(dist.containment_a, 0.5);
+        assert_eq!(dist.containment_b, 1.0);
+        assert_eq!(dist.containment(true), 1.0);
+        assert!((dist.ani - (1.0 + (2.0f64 / 3.0).ln() / 21.0)).abs() < 1e-12);
+
+        // similarity is symmetric, except for containment
+        let reverse = pairwise_distance(&genome2, &genome1, 21);
+        assert_eq!(reverse.jaccard, dist.jaccard);
+        assert_eq!(reverse.weighted_jaccard, dist.weighted_jaccard);
+        assert_eq!(reverse.containment_a, dist.containment_b);
+        assert_eq!(reverse.ani, dist.ani);
+
+        // identical and disjoint genomes
+        let same = pairwise_distance(&genome1, &genome1, 21);
+        assert_eq!((same.jaccard, same.weighted_jaccard, same.ani), (1.0, 1.0, 1.0));
+        let disjoint = pairwise_distance(&genome1, &hashes(&[(5, 1)]), 21);
+        assert_eq!((disjoint.jaccard, disjoint.containment_a, disjoint.ani), (0.0, 0.0, 0.0));
+        let empty = pairwise_distance(&Hashes::default(), &Hashes::default(), 21);
+        assert_eq!(empty.jaccard, 0.0);
+    }
+
+    #[test]
+    fn test_pairwise_distances() {
+        let genome1 = hashes(&[(1, 1), (2, 1)]);
+        let genome2 = hashes(&[(1, 1), (3, 1)]);
+        let genome3 = hashes(&[(4, 1)]);
+        let genomes = [&genome1, &genome2, &genome3];
+
+        let dists = pairwise_distances(&genomes, 21);
+        let pairs: Vec<(usize, usize)> = dists.iter().map(|(i, j, _)| (*i, *j)).collect();
+        assert_eq!(pairs, [(1, 0), (2, 0), (2, 1)]);
+        assert_eq!(dists[0].2.jaccard, 1.0 / 3.0);
+        assert_eq!(dists[2].2.jaccard, 0.0);
+    }
+}
@@ -3,44 +3,63 @@
 //! FracMinHash sketches from DNA sequences. FracMinHash is a probabilistic data structure
 //! used for efficient similarity estimation between large sets, such as k-mer sets from
 //! biological sequences. The implementation uses a scale factor to subsample hashes and
-//! supports counting both unique and weighted k-mers. The module depends on the `needletail`
+//! supports counting both unique and weighted k-mers. Unweighted sketches record only the presence of each
+//! k-mer, with every retained hash given a count of 1. K-mers of up to 32 bases are encoded as
+//! 64-bit integers, while longer k-mers of up to 64 bases use a 128-bit encoding. K-mers containing ambiguous bases can
+//! either be skipped or have the ambiguous bases treated as an A. The module depends on the `needletail`
 //! crate for sequence parsing and a custom hashing implementation for DNA k-mers.
 //! 
 //! See Hera et al., 2024: https://www.biorxiv.org/content/10.1101/2023.11.06.565843v3
 
 use needletail::parser::SequenceRecord;
 
-use crate::hashing::{dna_hashes, ItemHash, Hashes};
+use crate::hashing::{count_u64, dna_hashes, dna_hashes_128, ItemHash, Hashes, KmerCount};
 
 #[derive(Clone, Debug)]
 pub struct FracMinHash {
     hashes: Hashes,
     kmer_length: u8,
     max_hash: u64,
+    weighted: bool,
+    skip_ambiguous: bool,
     kmer_total_count: u64,
     bp_count: u64,
 }
 
 impl FracMinHash {
-    pub fn new(kmer_length: u8, scale: u64) -> Self {
+    pub fn new(kmer_length: u8, scale: u64, weighted: bool, skip_ambiguous: bool) -> Self {
         FracMinHash {
             hashes: Hashes::default(),
             kmer_length,
             max_hash: ItemHash::MAX / scale,
+            weighted,
+            skip_ambiguous,
             kmer_total_count: 0,
             bp_count: 0,
         }
     }
 
     pub fn process_seq(&mut self, seq: &SequenceRecord) {
-        self.bp_count += seq.num_bases() as u64;
-        self.kmer_total_count += seq.num_bases() as u64 - self.kmer_length as u64 + 1;
+        self.process_bytes(&seq.seq());
+    }
+
+    /// Process an in-memory nucleotide sequence without line breaks.
+    pub fn process_bytes(&mut self, seq: &[u8]) {
+        self.bp_count += seq.len() as u64;
+
+        // use 128-bit k-mer encoding only when required as it is slower
+        let hash_fn = if self.kmer_length <= 32 {
+            dna_hashes
+        } else {
+            dna_hashes_128
+        };
 
-        dna_hashes(
-            &seq.seq(),
+        self.kmer_total_count += hash_fn(
+            seq,
             &mut self.hashes,
             self.max_hash,
             self.kmer_length,
+            self.skip_ambiguous,
         );
     }
 
@@ -48,8 +67,23 @@ impl FracMinHash {
         self.hashes.len() as u64
     }
 
+    /// Number of hashes whose count has reached the maximum value of `KmerCount`.
+    ///
+    /// Counts are discarded by unweighted sketches, so no hashes are reported as saturated.
+    pub fn saturated_hash_count(&self) -> u64 {
+        if !self.weighted {
+            return 0;
+        }
+
+        self.hashes.values().filter(|&&v| v == KmerCount::MAX).count() as u64
+    }
+
     pub fn weighted_hash_count(&self) -> u64 {
-        self.hashes.values().map(|v| *v as u64).sum()
+        if !self.weighted {
+            return self.unique_hash_count();
+        }
+
+        self.hashes.values().map(|&v| count_u64(v)).sum()
     }
 
     pub fn kmer_total_count(&self) -> u64 {
@@ -60,7 +94,45 @@ impl FracMinHash {
         self.bp_count
     }
 
-    pub fn to_hashes(self) -> Hashes {
+    /// Retained hashes and their counts, with all counts set to 1 for unweighted sketches.
+    pub fn to_hashes(mut self) -> Hashes {
+        if !self.weighted {
+            self.hashes.values_mut().for_each(|count| *count = 1);
+        }
+
         self.hashes
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Weighted sketcher containing a single k-mer whose count has reached the maximum value.
+    pub(crate) fn saturated_sketcher() -> FracMinHash {
+        let mut sketcher = FracMinHash::new(3, 1, true, true);
+        sketcher.process_bytes(b"AAA");
+        sketcher.hashes.values_mut().for_each(|count| *count = KmerCount::MAX - 1);
+
+        // two further occurrences of the k-mer saturate its count instead of overflowing
+        sketcher.process_bytes(b"AAAA");
+        sketcher
+    }
+
+    #[test]
+    fn test_saturated_hash_count() {
+        let sketcher = saturated_sketcher();
+        assert_eq!(sketcher.unique_hash_count(), 1);
+        assert_eq!(sketcher.saturated_hash_count(), 1);
+        assert_eq!(sketcher.to_hashes().into_values().collect::<Vec<_>>(), [KmerCount::MAX]);
+
+        let mut sketcher = FracMinHash::new(3, 1, true, true);
+        sketcher.process_bytes(b"AAAAAA");
+        assert_eq!(sketcher.saturated_hash_count(), 0);
+
+        // counts are discarded by unweighted sketches, so no hashes are saturated
+        let mut sketcher = saturated_sketcher();
+        sketcher.weighted = false;
+        assert_eq!(sketcher.saturated_hash_count(), 0);
+    }
+}
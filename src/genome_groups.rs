@@ -0,0 +1,412 @@
+//! This module determines the groups of genomes to process from a genome group table or an input directory.
+//!
+//! A genome group table is a TSV file with a row for each genome in a group. Without a header, rows
+//! contain the group identifier, the path to the genome file, and optionally a genome identifier.
+//! With a header, columns are identified by name: `group_id` and the genome file path (`genome_file`,
+//! `fasta_file_path`, or `path`) are required, `genome_id` is optional, and any other columns are
+//! treated as metadata which is passed through to the per-genome output. Metadata columns must have
+//! unique names which differ from those of the per-genome output columns. The header may be
+//! prefixed with `#`; other lines starting with `#` are treated as comments.
+//!
+//! Genome identifiers default to the file name of each genome with extensions removed.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+
+use crate::output::GENOME_COLUMNS;
+
+/// Names of the column containing the path to genome files.
+const GENOME_FILE_COLUMNS: [&str; 3] = ["genome_file", "fasta_file_path", "path"];
+
+/// Index of each column in a genome group table.
+#[derive(Clone, Debug, PartialEq)]
+struct ColumnMap {
+    group_id: usize,
+    genome_file: usize,
+    genome_id: Option<usize>,
+    metadata: Vec<(usize, String)>,
+    num_columns: usize,
+}
+
+impl ColumnMap {
+    /// Columns of a table without a header.
+    fn positional(num_columns: usize) -> Result<Self> {
+        if num_columns != 2 && num_columns != 3 {
+            bail!("Invalid TSV format: each line must have 2 or 3 columns (group_id, path, and optional genome_id) unless a header is provided");
+        }
+
+        Ok(ColumnMap {
+            group_id: 0,
+            genome_file: 1,
+            genome_id: (num_columns == 3).then_some(2),
+            metadata: Vec::new(),
+            num_columns,
+        })
+    }
+
+    /// Columns identified from the fields of a header line, or None if the fields are not a header.
+    fn from_header(fields: &[&str]) -> Option<Self> {
+        let position = |name: &str| fields.iter().position(|field| *field == name);
+
+        let group_id = position("group_id")?;
+        let genome_file = GENOME_FILE_COLUMNS.iter().find_map(|name| position(name))?;
+        let genome_id = position("genome_id");
+
+        let metadata = fields
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != group_id && *idx != genome_file && Some(*idx) != genome_id)
+            .map(|(idx, name)| (idx, name.to_string()))
+            .collect();
+
+        Some(ColumnMap {
+            group_id,
+            genome_file,
+            genome_id,
+            metadata,
+            num_columns: fields.len(),
+        })
+    }
+
+    /// Verify that names of metadata columns are unique and distinct from the columns of per-genome output.
+    fn check_metadata_columns(&self, line_number: usize) -> Result<()> {
+        for (idx, (_, name)) in self.metadata.iter().enumerate() {
+            if GENOME_COLUMNS.contains(&name.as_str()) {
+                bail!(
+                    "Metadata column '{}' in the header on line {} has the same name as a column of the per-genome output.",
+                    name,
+                    line_number
+                );
+            }
+            if self.metadata[..idx].iter().any(|(_, other)| other == name) {
+                bail!("Metadata column '{}' appears more than once in the header on line {}.", name, line_number);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Groups of genomes, along with any genome identifiers and metadata specified for each genome.
+#[derive(Clone, Debug, Default)]
+pub struct GenomeGroups {
+    groups: Vec<(String, Vec<PathBuf>)>,
+    genome_ids: HashMap<PathBuf, String>,
+    metadata_columns: Vec<String>,
+    metadata: HashMap<(String, PathBuf), Vec<String>>,
+}
+
+impl GenomeGroups {
+    /// Parse a TSV file containing genome groups and the path to their genome files.
+    ///
+    /// Groups, and the genomes within each group, are kept in the order they first appear in the file.
+    pub fn from_table(file_path: &Path) -> Result<Self> {
+        let file = File::open(file_path)
+            .context(format!("Failed to open {}", file_path.display()))?;
+        let reader = BufReader::new(file);
+
+        let mut genome_groups = GenomeGroups::default();
+        let mut group_index: HashMap<String, usize> = HashMap::new();
+        let mut columns: Option<ColumnMap> = None;
+        for (line_idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = line_idx + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // header must precede all genomes and may be prefixed with #
+            if columns.is_none() {
+                let header = line.trim_start_matches('#').trim();
+                let fields: Vec<&str> = header.split('\t').map(str::trim).collect();
+                if let Some(header_columns) = ColumnMap::from_header(&fields) {
+                    header_columns.check_metadata_columns(line_number)?;
+                    genome_groups.metadata_columns = header_columns.metadata.iter().map(|(_, name)| name.clone()).collect();
+                    columns = Some(header_columns);
+                    continue;
+                } else if !line.starts_with('#') && fields.contains(&"group_id") {
+                    bail!(
+                        "Header on line {} must contain a genome file column named one of: {}",
+                        line_number,
+                        GENOME_FILE_COLUMNS.join(", ")
+                    );
+                }
+            }
+
+            // skip comment lines starting with #
+            if line.starts_with('#') {
+                continue;
+            }
+
+            // whitespace is trimmed from each cell, rather than the line, so trailing empty cells are retained
+            let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+            let columns = match &columns {
+                Some(columns) => columns,
+                None => columns.insert(ColumnMap::positional(fields.len())?),
+            };
+
+            if fields.len() != columns.num_columns {
+                bail!(
+                    "Invalid TSV format: line {} has {} columns, but {} columns were expected",
+                    line_number,
+                    fields.len(),
+                    columns.num_columns
+                );
+            }
+
+            let group = fields[columns.group_id].to_string();
+            let path = PathBuf::from(fields[columns.genome_file]);
+
+            if let Some(genome_id) = columns.genome_id.map(|idx| fields[idx]).filter(|id| !id.is_empty())
+                && let Some(other_id) = genome_groups.genome_ids.insert(path.clone(), genome_id.to_string())
+                && other_id != genome_id {
+                bail!(
+                    "Genome file {} is given the genome identifiers '{}' and '{}'.",
+                    path.display(),
+                    other_id,
+                    genome_id
+                );
+            }
+
+            if !columns.metadata.is_empty() {
+                let metadata = columns.metadata.iter().map(|(idx, _)| fields[*idx].to_string()).collect();
+                genome_groups.metadata.insert((group.clone(), path.clone()), metadata);
+            }
+
+            let idx = *group_index.entry(group.clone()).or_insert_with(|| {
+                genome_groups.groups.push((group, Vec::new()));
+                genome_groups.groups.len() - 1
+            });
+            genome_groups.groups[idx].1.push(path);
+        }
+
+        genome_groups.check_unique_genome_ids()?;
+
+        Ok(genome_groups)
+    }
+
+    /// Place all genome files in a directory into a single group.
+    ///
+    /// Genome files are sorted by path as directory listings are returned in an arbitrary order.
+    pub fn from_dir(input_dir: &Path, is_genome_file: impl Fn(&Path) -> bool) -> Result<Self> {
+        let paths: Vec<PathBuf> = std::fs::read_dir(input_dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| is_genome_file(&entry.path()))
+            .map(|entry| entry.path())
+            .sorted()
+            .collect();
+
+        if paths.is_empty() {
+            bail!("No FASTA files found in specified directory.");
+        }
+
+        Ok(GenomeGroups {
+            groups: vec![("default".to_string(), paths)],
+            ..Default::default()
+        })
+    }
+
+    /// Groups of genomes and the paths to their genome files.
+    pub fn groups(&self) -> &[(String, Vec<PathBuf>)] {
+        &self.groups
+    }
+
+    /// Number of groups.
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Return true if there are no groups.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Unique genome files across all groups.
+    pub fn genome_files(&self) -> Vec<&PathBuf> {
+        self.groups.iter().flat_map(|(_, paths)| paths).unique().collect()
+    }
+
+    /// Genome identifiers explicitly specified for genome files.
+    pub fn genome_ids(&self) -> &HashMap<PathBuf, String> {
+        &self.genome_ids
+    }
+
+    /// Genome identifier explicitly specified for a genome file.
+    pub fn genome_id(&self, genome_file: &Path) -> Option<&str> {
+        self.genome_ids.get(genome_file).map(String::as_str)
+    }
+
+    /// Names of metadata columns.
+    pub fn metadata_columns(&self) -> &[String] {
+        &self.metadata_columns
+    }
+
+    /// Value of a metadata column for a genome in a group.
+    pub fn metadata_value(&self, group_id: &str, genome_file: &Path, column: &str) -> Option<&str> {
+        let column_idx = self.metadata_columns.iter().position(|name| name == column)?;
+        self.metadata(group_id, genome_file).map(|metadata| metadata[column_idx].as_str())
+    }
+
+    /// Metadata for a genome in a group.
+    pub fn metadata(&self, group_id: &str, genome_file: &Path) -> Option<&[String]> {
+        self.metadata
+            .get(&(group_id.to_string(), genome_file.to_path_buf()))
+            .map(Vec::as_slice)
+    }
+
+    /// Verify that explicitly specified genome identifiers are not shared by different genome files.
+    fn check_unique_genome_ids(&self) -> Result<()> {
+        let mut genome_files: HashMap<&str, &Path> = HashMap::new();
+        for (genome_file, genome_id) in self.genome_ids.iter().sorted() {
+            if let Some(other_file) = genome_files.insert(genome_id, genome_file) {
+                bail!(
+                    "Genome files {} and {} are both given the genome identifier '{}'.",
+                    other_file.display(),
+                    genome_file.display(),
+                    genome_id
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+    use tempfile::NamedTempFile;
+
+    fn parse(content: &str) -> Result<GenomeGroups> {
+        let temp_file = NamedTempFile::new()?;
+        write(temp_file.path(), content)?;
+        GenomeGroups::from_table(temp_file.path())
+    }
+
+    #[test]
+    fn test_parse_genome_groups_file() -> Result<()> {
+        let groups = parse(
+            "# group_id\tpath\n\
+             group2\t/path/to/genome3.fna\n\
+             group1\t/path/to/genome1.fna\n\
+             group2\t/path/to/genome4.fna\n\
+             group1\t/path/to/genome2.fna",
+        )?;
+
+        // groups and genomes are in the order they first appear
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups.groups()[0].0, "group2");
+        assert_eq!(groups.groups()[1].0, "group1");
+        assert_eq!(groups.groups()[0].1, vec![PathBuf::from("/path/to/genome3.fna"), PathBuf::from("/path/to/genome4.fna")]);
+        assert_eq!(groups.groups()[1].1, vec![PathBuf::from("/path/to/genome1.fna"), PathBuf::from("/path/to/genome2.fna")]);
+        assert!(groups.genome_ids().is_empty());
+        assert!(groups.metadata_columns().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_positional_genome_ids() -> Result<()> {
+        let groups = parse(
+            "# comment\n\
+             group1\t/path/to/genome1.fna\tGCF_1\n\
+             group1\t/path/to/genome2.fna\tGCF_2\n\
+             group2\t/path/to/genome1.fna\tGCF_1",
+        )?;
+
+        assert_eq!(groups.genome_files().len(), 2);
+        assert_eq!(groups.genome_id(Path::new("/path/to/genome1.fna")), Some("GCF_1"));
+        assert_eq!(groups.genome_id(Path::new("/path/to/genome2.fna")), Some("GCF_2"));
+
+        // inconsistent number of columns
+        assert!(parse("group1\t/path/to/genome1.fna\ngroup1\t/path/to/genome2.fna\tGCF_2").is_err());
+        assert!(parse("group1\t/path/to/genome1.fna\tGCF_1\tBacteria").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_columns() -> Result<()> {
+        let groups = parse(
+            "genome_id\ttaxonomy\tgenome_file\tsource\tgroup_id\n\
+             GCF_1\td__Bacteria\t/path/to/genome1.fna\tisolate\tgroup1\n\
+             GCF_2\td__Archaea\t/path/to/genome2.fna\tMAG\tgroup1",
+        )?;
+
+        assert_eq!(groups.groups()[0].1, vec![PathBuf::from("/path/to/genome1.fna"), PathBuf::from("/path/to/genome2.fna")]);
+        assert_eq!(groups.genome_id(Path::new("/path/to/genome2.fna")), Some("GCF_2"));
+        assert_eq!(groups.metadata_columns(), ["taxonomy", "source"]);
+        assert_eq!(
+            groups.metadata("group1", Path::new("/path/to/genome2.fna")),
+            Some(["d__Archaea".to_string(), "MAG".to_string()].as_slice())
+        );
+        assert_eq!(groups.metadata("group2", Path::new("/path/to/genome2.fna")), None);
+        assert_eq!(groups.metadata_value("group1", Path::new("/path/to/genome1.fna"), "source"), Some("isolate"));
+        assert_eq!(groups.metadata_value("group1", Path::new("/path/to/genome1.fna"), "genome_id"), None);
+
+        // header without a genome file column
+        assert!(parse("group_id\tfile_name\ngroup1\t/path/to/genome1.fna").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_metadata() -> Result<()> {
+        let groups = parse(
+            "group_id\tgenome_file\tdomain\tsource\r\n\
+             group1\t/path/to/genome1.fna\td__Archaea\t\r\n\
+             group1\t/path/to/genome2.fna\t\t\n",
+        )?;
+
+        assert_eq!(groups.metadata_value("group1", Path::new("/path/to/genome1.fna"), "domain"), Some("d__Archaea"));
+        assert_eq!(groups.metadata_value("group1", Path::new("/path/to/genome1.fna"), "source"), Some(""));
+        assert_eq!(
+            groups.metadata("group1", Path::new("/path/to/genome2.fna")),
+            Some(["".to_string(), "".to_string()].as_slice())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_column_names() {
+        assert!(parse("group_id\tgenome_file\thabitat\tgenome_file_size\ngroup1\t/path/to/genome1.fna\tsoil\t10\n").is_ok());
+
+        // names of per-genome output columns and repeated names
+        for column in ["weight", "entropy", "delta_khill"] {
+            let result = parse(&format!("group_id\tgenome_file\t{column}\ngroup1\t/path/to/genome1.fna\t1\n"));
+            assert!(result.unwrap_err().to_string().contains(&format!("'{column}'")));
+        }
+        assert!(parse("group_id\tgenome_file\thabitat\thabitat\ngroup1\t/path/to/genome1.fna\tsoil\tgut\n").is_err());
+    }
+
+    #[test]
+    fn test_whitespace_around_cells() -> Result<()> {
+        let groups = parse(" group1\t/path/to/genome1.fna  \r\ngroup1 \t /path/to/genome2.fna\n")?;
+        assert_eq!(groups.groups(), [(
+            "group1".to_string(),
+            vec![PathBuf::from("/path/to/genome1.fna"), PathBuf::from("/path/to/genome2.fna")],
+        )]);
+
+        let groups = parse("group_id\tgenome_file\tsource\ngroup1\t/path/to/genome1.fna \t clinical \n")?;
+        assert_eq!(groups.genome_files(), [&PathBuf::from("/path/to/genome1.fna")]);
+        assert_eq!(groups.metadata_value("group1", Path::new("/path/to/genome1.fna"), "source"), Some("clinical"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_genome_ids() {
+        // same genome file with different identifiers
+        assert!(parse("group1\t/path/to/genome1.fna\tGCF_1\ngroup2\t/path/to/genome1.fna\tGCF_2").is_err());
+
+        // different genome files with the same identifier
+        assert!(parse("group1\t/path/to/genome1.fna\tGCF_1\ngroup1\t/path/to/genome2.fna\tGCF_1").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! This module calculates K-Hill and related statistics for many groups of genomes concurrently.
+//!
+//! Groups are processed in parallel, while calculations within each group are also parallelized
+//! across genomes. Rayon's work-stealing scheduler balances the two levels of parallelism so that
+//! tables with many small groups and tables with a few large groups both make use of all threads.
+//! Results are returned in the same order as the input groups so output files are written
+//! deterministically regardless of the order in which groups finish. Groups and the genomes
+//! within them can also be sorted by identifier or by value.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use indicatif::ProgressBar;
+use itertools::Itertools;
+use log::warn;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::bootstrap::{bootstrap_khill, summarize_replicates, BootstrapSummary};
+use crate::hashing::Hashes;
+use crate::khill::{khill_from_hashes, KHillResult};
+use crate::rarefaction::{rarefaction_curve, RarefactionPoint};
+use crate::sketch_cache::SketchCache;
+
+/// Order of groups and genomes in output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Order in which groups and genomes were specified
+    Input,
+    /// Sorted by group and genome identifiers
+    Id,
+    /// Sorted by decreasing K-Hill of groups and beta entropy of genomes
+    Value,
+}
+
+/// Parameters controlling which statistics are calculated for each group.
+#[derive(Clone, Debug, Default)]
+pub struct AnalysisParams {
+    pub hill_orders: Vec<f64>,
+    pub bootstrap_replicates: usize,
+    pub confidence: f64,
+    pub rarefaction_step: usize,
+    pub rarefaction_replicates: usize,
+    pub seed: u64,
+}
+
+/// Results for a single group of genomes.
+#[derive(Clone, Debug)]
+pub struct GroupAnalysis {
+    pub group_id: String,
+    pub num_genomes: usize,
+    pub genomes: Vec<(String, PathBuf)>,
+    pub khill: KHillResult,
+    pub bootstrap: Option<BootstrapSummary>,
+    pub rarefaction: Option<Vec<RarefactionPoint>>,
+}
+
+impl GroupAnalysis {
+    /// Genome identifiers and genome files in the specified output order.
+    ///
+    /// Input order is the order in which genomes were listed for the group.
+    pub fn sorted_genomes(&self, sort_by: SortOrder) -> Vec<(&String, &PathBuf)> {
+        let mut genomes: Vec<(&String, &PathBuf)> = self.genomes.iter().map(|(id, file)| (id, file)).collect();
+        match sort_by {
+            SortOrder::Input => {}
+            SortOrder::Id => genomes.sort_by(|a, b| a.0.cmp(b.0)),
+            SortOrder::Value => {
+                let beta_entropy = |genome_id: &String| {
+                    let components = &self.khill.genome_components[genome_id];
+                    components.weight * components.kl_divergence
+                };
+                genomes.sort_by(|a, b| beta_entropy(b.0).total_cmp(&beta_entropy(a.0)).then_with(|| a.0.cmp(b.0)));
+            }
+        }
+
+        genomes
+    }
+}
+
+/// Sort results for groups into the specified output order.
+///
+/// Sorting is stable so groups with the same K-Hill remain in input order.
+pub fn sort_groups(results: &mut [GroupAnalysis], sort_by: SortOrder) {
+    match sort_by {
+        SortOrder::Input => {}
+        SortOrder::Id => results.sort_by(|a, b| a.group_id.cmp(&b.group_id)),
+        SortOrder::Value => results.sort_by(|a, b| b.khill.khill().total_cmp(&a.khill.khill())),
+    }
+}
+
+/// Analyze groups of genomes in parallel, returning results in the order of the input groups.
+///
+/// Groups where none of the genomes could be sketched are skipped.
+pub fn analyze_groups(
+    groups: &[(&String, &Vec<PathBuf>)],
+    sketch_cache: &SketchCache,
+    params: &AnalysisParams,
+    progress_bar: &ProgressBar,
+) -> Result<Vec<GroupAnalysis>> {
+    let results: Vec<Option<GroupAnalysis>> = groups
+        .par_iter()
+        .map(|(group_id, genome_paths)| {
+            let result = analyze_group(group_id, genome_paths, sketch_cache, params);
+            progress_bar.inc(1);
+            result
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Analyze a single group of genomes.
+fn analyze_group(
+    group_id: &str,
+    genome_paths: &[PathBuf],
+    sketch_cache: &SketchCache,
+    params: &AnalysisParams,
+) -> Result<Option<GroupAnalysis>> {
+    let genome_hashes = sketch_cache.genome_hashes(genome_paths)?;
+    if genome_hashes.is_empty() {
+        warn!("Skipping group '{}' as none of its genomes could be sketched.", group_id);
+        return Ok(None);
+    }
+
+    let khill = khill_from_hashes(&genome_hashes, &params.hill_orders);
+    let genomes = sketch_cache.cached_genomes(genome_paths);
+
+    // resampling requires genomes in a consistent order so results are reproducible
+    let hashes: Vec<&Hashes> = genome_hashes.iter()
+        .sorted_by(|a, b| a.0.cmp(b.0))
+        .map(|(_genome_id, hashes)| hashes.as_ref())
+        .collect();
+
+    let bootstrap = (params.bootstrap_replicates > 0).then(|| {
+        let replicates = bootstrap_khill(&hashes, params.bootstrap_replicates, params.seed);
+        summarize_replicates(&replicates, params.confidence)
+    });
+
+    let rarefaction = (params.rarefaction_step > 0).then(|| {
+        rarefaction_curve(&hashes, params.rarefaction_step, params.rarefaction_replicates, params.seed)
+    });
+
+    Ok(Some(GroupAnalysis {
+        group_id: group_id.to_string(),
+        num_genomes: genome_hashes.len(),
+        genomes,
+        khill,
+        bootstrap,
+        rarefaction,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sketch_params::SketchParams;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_analyze_groups_preserves_order() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut genome_files = Vec::new();
+        for (idx, seq) in ["ACGTACGTACGT", "ACGTACGTACGA", "TTGACCAGTAGG"].iter().enumerate() {
+            let genome_file = temp_dir.path().join(format!("genome{}.fna", idx + 1));
+            std::fs::write(&genome_file, format!(">seq\n{}\n", seq))?;
+            genome_files.push(genome_file);
+        }
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let files: Vec<&PathBuf> = genome_files.iter().collect();
+        let (sketch_cache, _) = SketchCache::build(&files, &HashMap::new(), &sketch_params, None, temp_dir.path())?;
+
+        // many small groups, including one whose only genome is not in the cache
+        let missing = vec![temp_dir.path().join("missing.fna")];
+        let group_ids: Vec<String> = (0..50).map(|idx| format!("group{}", idx)).collect();
+        let group_genomes: Vec<Vec<PathBuf>> = (0..50)
+            .map(|idx| vec![genome_files[idx % 3].clone(), genome_files[(idx + 1) % 3].clone()])
+            .collect();
+        let mut groups: Vec<(&String, &Vec<PathBuf>)> = group_ids.iter().zip(group_genomes.iter()).collect();
+        let missing_id = "missing".to_string();
+        groups.insert(10, (&missing_id, &missing));
+
+        let params = AnalysisParams {
+            bootstrap_replicates: 5,
+            confidence: 0.95,
+            ..Default::default()
+        };
+        let results = analyze_groups(&groups, &sketch_cache, &params, &ProgressBar::hidden())?;
+
+        assert_eq!(results.len(), 50);
+        for (result, group_id) in results.iter().zip(&group_ids) {
+            assert_eq!(&result.group_id, group_id);
+            assert_eq!(result.num_genomes, 2);
+            assert!(result.bootstrap.is_some());
+            assert!(result.rarefaction.is_none());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_order() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut genome_files = Vec::new();
+        for (genome_id, seq) in [("c", "ACGTACGTACGT"), ("a", "ACGTACGTACGA"), ("b", "TTGACCAGTAGG")] {
+            let genome_file = temp_dir.path().join(format!("{}.fna", genome_id));
+            std::fs::write(&genome_file, format!(">seq\n{}\n", seq))?;
+            genome_files.push(genome_file);
+        }
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let files: Vec<&PathBuf> = genome_files.iter().collect();
+        let (sketch_cache, _) = SketchCache::build(&files, &HashMap::new(), &sketch_params, None, temp_dir.path())?;
+
+        // group2 contains more distinct genomes so has a larger K-Hill
+        let group_ids = ["group2".to_string(), "group1".to_string()];
+        let group_genomes = [genome_files.clone(), genome_files[..2].to_vec()];
+        let groups: Vec<(&String, &Vec<PathBuf>)> = group_ids.iter().zip(group_genomes.iter()).collect();
+        let mut results = analyze_groups(&groups, &sketch_cache, &AnalysisParams::default(), &ProgressBar::hidden())?;
+
+        sort_groups(&mut results, SortOrder::Input);
+        assert_eq!(results[0].group_id, "group2");
+        let genome_ids = |sort_by| -> Vec<String> {
+            results[0].sorted_genomes(sort_by).into_iter().map(|(id, _)| id.clone()).collect()
+        };
+        assert_eq!(genome_ids(SortOrder::Input), ["c", "a", "b"]);
+        assert_eq!(genome_ids(SortOrder::Id), ["a", "b", "c"]);
+
+        let by_value = genome_ids(SortOrder::Value);
+        let beta_entropy = |genome_id: &String| {
+            let components = &results[0].khill.genome_components[genome_id];
+            components.weight * components.kl_divergence
+        };
+        assert!(by_value.windows(2).all(|w| beta_entropy(&w[0]) >= beta_entropy(&w[1])));
+
+        sort_groups(&mut results, SortOrder::Id);
+        assert_eq!(results[0].group_id, "group1");
+
+        sort_groups(&mut results, SortOrder::Value);
+        assert_eq!(results[0].group_id, "group2");
+        assert!(results[0].khill.khill() > results[1].khill.khill());
+
+        Ok(())
+    }
+}
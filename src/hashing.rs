@@ -29,14 +29,36 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::ops::{BitAnd, BitOr, Shl, Shr};
+
 use rustc_hash::FxHashMap;
 
 pub type ItemHash = u64;
+
+// Width of k-mer counts can be selected at compile time. Counts are 32 bits by default
+// which requires the same memory as 16-bit counts since hash table entries are padded
+// to the alignment of ItemHash. Features are additive, so the widest enabled width is used.
+#[cfg(feature = "count-u64")]
+pub type KmerCount = u64;
+#[cfg(all(feature = "count-u16", not(feature = "count-u64")))]
 pub type KmerCount = u16;
+#[cfg(not(any(feature = "count-u16", feature = "count-u64")))]
+pub type KmerCount = u32;
+
 pub type Hashes = FxHashMap<ItemHash, KmerCount>;
 
+/// Widen a k-mer count to 64 bits so counts can be summed without overflow.
+#[allow(clippy::useless_conversion)] // KmerCount is already 64 bits with the count-u64 feature
+#[inline]
+pub fn count_u64(count: KmerCount) -> u64 {
+    u64::from(count)
+}
+
+/// Value of ambiguous (non-ACGT) bases in `NT_TO_BYTE`.
+const AMBIGUOUS_NT: u8 = 4;
+
 const NT_TO_BYTE: [u8; 256] = {
-    let mut table = [0; 256];
+    let mut table = [AMBIGUOUS_NT; 256];
 
     table[b'A' as usize] = 0;
     table[b'C' as usize] = 1;
@@ -73,45 +95,130 @@ pub fn tw_hash64(kmer: ItemHash) -> ItemHash {
     hash
 }
 
+/// Hash 128-bit k-mer to a 64-bit value using Thomas Wang's integer hash function.
+// The high bits are hashed and mixed into the low bits before the final hash. Since
+// tw_hash64 is invertible, k-mers differing only in their low bits never collide.
+#[inline]
+pub fn tw_hash128(kmer: u128) -> ItemHash {
+    let hi = (kmer >> 64) as u64;
+    let lo = kmer as u64;
+
+    tw_hash64(lo ^ tw_hash64(hi))
+}
+
+/// Integer type used to encode k-mers with 2 bits per base.
+trait KmerBits:
+    Copy
+    + Ord
+    + From<u8>
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+    + BitOr<Output = Self>
+    + BitAnd<Output = Self>
+{
+    const MAX: Self;
+    const BITS: usize;
+
+    /// Hash k-mer to a 64-bit value.
+    fn hash(self) -> ItemHash;
+}
+
+impl KmerBits for u64 {
+    const MAX: Self = u64::MAX;
+    const BITS: usize = 64;
+
+    #[inline]
+    fn hash(self) -> ItemHash {
+        tw_hash64(self)
+    }
+}
+
+impl KmerBits for u128 {
+    const MAX: Self = u128::MAX;
+    const BITS: usize = 128;
+
+    #[inline]
+    fn hash(self) -> ItemHash {
+        tw_hash128(self)
+    }
+}
+
 /// Determine hashes in sequence satisfying maximum k-mer hash criterion.
-// Modified from the fmh_seeds method by Jim Shaw in skani.
+/// 
+/// K-mers are encoded as 64-bit integers so k must be at most 32. K-mers containing
+/// ambiguous (non-ACGT) bases are skipped if `skip_ambiguous` is true, otherwise
+/// ambiguous bases are treated as an A. Returns the number of k-mers considered.
 pub fn dna_hashes(
     seq: &[u8],
     hashes: &mut Hashes,
     max_hash: ItemHash,
     k: u8,
-) {
+    skip_ambiguous: bool,
+) -> u64 {
+    kmer_hashes::<u64>(seq, hashes, max_hash, k, skip_ambiguous)
+}
+
+/// Determine hashes in sequence satisfying maximum k-mer hash criterion for k-mers of up to 64 bases.
+/// 
+/// K-mers are encoded as 128-bit integers and hashed to 64-bit values. This is slower than
+/// `dna_hashes` so should only be used for k > 32.
+pub fn dna_hashes_128(
+    seq: &[u8],
+    hashes: &mut Hashes,
+    max_hash: ItemHash,
+    k: u8,
+    skip_ambiguous: bool,
+) -> u64 {
+    kmer_hashes::<u128>(seq, hashes, max_hash, k, skip_ambiguous)
+}
+
+// Modified from the fmh_seeds method by Jim Shaw in skani.
+fn kmer_hashes<K: KmerBits>(
+    seq: &[u8],
+    hashes: &mut Hashes,
+    max_hash: ItemHash,
+    k: u8,
+    skip_ambiguous: bool,
+) -> u64 {
     let k = k as usize;
 
     if seq.len() < k {
-        return;
+        return 0;
     }
 
-    let mut fwd_kmer: ItemHash = 0;
-    let mut rev_kmer: ItemHash = 0;
+    let mut fwd_kmer = K::from(0);
+    let mut rev_kmer = K::from(0);
 
     let rev_shift_dist = 2 * (k - 1);
-    let fwd_mask = ItemHash::MAX >> (std::mem::size_of::<ItemHash>() * 8 - 2 * k);
+    let fwd_mask = K::MAX >> (K::BITS - 2 * k);
 
-    for i in 0..k - 1 {
-        let nuc_f = NT_TO_BYTE[seq[i] as usize] as ItemHash;
-        fwd_kmer <<= 2;
-        fwd_kmer |= nuc_f;
+    // number of consecutive bases contributing to the current k-mer
+    let mut valid_len = 0;
+    let mut num_kmers = 0;
 
-        let nuc_r = 3 - nuc_f;
-        rev_kmer >>= 2;
-        rev_kmer |= nuc_r << rev_shift_dist;
-    }
+    for &base in seq {
+        let mut nuc = NT_TO_BYTE[base as usize];
+        if nuc == AMBIGUOUS_NT {
+            if skip_ambiguous {
+                valid_len = 0;
+                continue;
+            }
+
+            nuc = 0;
+        }
 
-    for i in k - 1..seq.len() {
-        let nuc_f = NT_TO_BYTE[seq[i] as usize] as ItemHash;
-        fwd_kmer <<= 2;
-        fwd_kmer |= nuc_f;
-        fwd_kmer &= fwd_mask;
+        let nuc_f = K::from(nuc);
+        fwd_kmer = ((fwd_kmer << 2) | nuc_f) & fwd_mask;
 
-        let nuc_r = 3 - nuc_f;
-        rev_kmer >>= 2;
-        rev_kmer |= nuc_r << rev_shift_dist;
+        let nuc_r = K::from(3 - nuc);
+        rev_kmer = (rev_kmer >> 2) | (nuc_r << rev_shift_dist);
+
+        valid_len += 1;
+        if valid_len < k {
+            continue;
+        }
+
+        num_kmers += 1;
 
         let canonical_kmer_marker = if fwd_kmer < rev_kmer {
             fwd_kmer
@@ -119,18 +226,25 @@ pub fn dna_hashes(
             rev_kmer
         };
 
-        let hash = tw_hash64(canonical_kmer_marker);
+        let hash = canonical_kmer_marker.hash();
         if hash < max_hash {
             let count = hashes.entry(hash).or_insert(0);
             *count = count.saturating_add(1);
         }
     }
+
+    num_kmers
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
+    /// Hashes with the specified k-mer counts.
+    pub(crate) fn hashes(counts: &[(ItemHash, KmerCount)]) -> Hashes {
+        counts.iter().copied().collect()
+    }
+
     // Inverse of Thomas Wang's integer hash function.
     // https://aebou.rbind.io/post/a-rust-glimpse-at-thomas-wang-integer-hash-function
     fn tw_hash64i(hashed_key: u64) -> u64 {
@@ -182,22 +296,22 @@ mod tests {
     fn test_bit_kmer_value() {
         let mut hashes = Hashes::default();
 
-        dna_hashes(b"AAAA", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"AAAA", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(tw_hash64(0), *hashes.iter().next().unwrap().0); // AAAA = 00000000b = 0
 
         hashes.clear();
-        dna_hashes(b"TTTT", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"TTTT", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(tw_hash64(0), *hashes.iter().next().unwrap().0); // AAAA = 0 < TTTT
 
         hashes.clear();
-        dna_hashes(b"CCCC", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"CCCC", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(tw_hash64(85), *hashes.iter().next().unwrap().0); // CCCC = 01010101b = 85
 
         hashes.clear();
-        dna_hashes(b"GGGG", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"GGGG", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(tw_hash64(85), *hashes.iter().next().unwrap().0); // CCCC = 85 < GGGG
     }
@@ -205,25 +319,25 @@ mod tests {
     #[test]
     fn test_canonical_kmer() {
         let mut hashes = Hashes::default();
-        dna_hashes(b"AAAAAAAA", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"AAAAAAAA", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
         assert_eq!(hashes.get(&tw_hash64(0)), Some(&5));
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"TTTTTTTT", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"TTTTTTTT", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
         assert_eq!(hashes.get(&tw_hash64(0)), Some(&5));
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"CCCCCCCC", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"CCCCCCCC", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
         assert_eq!(hashes.get(&tw_hash64(85)), Some(&5));
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"GGGGGGGG", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"GGGGGGGG", &mut hashes, u64::MAX, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
         assert_eq!(hashes.get(&tw_hash64(85)), Some(&5));
@@ -232,7 +346,7 @@ mod tests {
     #[test]
     fn test_simple_seq() {
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, u64::MAX, 4, true);
 
         // kmer | rev  | smallest | binary    | decimal
         // ACGT | ACGT | ACGT     | 00011011b | 27
@@ -254,7 +368,7 @@ mod tests {
         // should produce same restul as test_simple_seq()
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"AcgTaCGt", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"AcgTaCGt", &mut hashes, u64::MAX, 4, true);
 
         assert_eq!(hashes.len(), 3);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
@@ -266,12 +380,12 @@ mod tests {
 
     #[test]
     fn test_ambiguous_bases() {
-        // Ambiguous bases are treated as an A; a common convention
-        // in high performance bioinformatic software. Should produce
-        // same results as test_simple_seq().
+        // Ambiguous bases are treated as an A when they are not being
+        // skipped; a common convention in high performance bioinformatic
+        // software. Should produce same results as test_simple_seq().
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"NCGTnCGT", &mut hashes, u64::MAX, 4);
+        dna_hashes(b"NCGTnCGT", &mut hashes, u64::MAX, 4, false);
 
         assert_eq!(hashes.len(), 3);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
@@ -295,32 +409,32 @@ mod tests {
         assert_eq!(tw_hash64(177), 8958356766268387398);
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, 8958356766268387398, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, 8958356766268387398, 4, true);
         assert_eq!(hashes.len(), 0);
         assert_eq!(hashes.values().sum::<KmerCount>(), 0);
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, 8958356766268387398 + 1, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, 8958356766268387398 + 1, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(hashes.values().sum::<KmerCount>(), 1);
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, 12564563040126408309, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, 12564563040126408309, 4, true);
         assert_eq!(hashes.len(), 1);
         assert_eq!(hashes.values().sum::<KmerCount>(), 1);
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, 12564563040126408309 + 1, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, 12564563040126408309 + 1, 4, true);
         assert_eq!(hashes.len(), 2);
         assert_eq!(hashes.values().sum::<KmerCount>(), 3);
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, 13364770925836396135, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, 13364770925836396135, 4, true);
         assert_eq!(hashes.len(), 2);
         assert_eq!(hashes.values().sum::<KmerCount>(), 3);
 
         let mut hashes = Hashes::default();
-        dna_hashes(b"ACGTACGT", &mut hashes, 13364770925836396135 + 1, 4);
+        dna_hashes(b"ACGTACGT", &mut hashes, 13364770925836396135 + 1, 4, true);
         assert_eq!(hashes.len(), 3);
         assert_eq!(hashes.values().sum::<KmerCount>(), 5);
     }
@@ -338,7 +452,7 @@ mod tests {
 
         let mut hashes = Hashes::default();
 
-        dna_hashes(b"ACGTTT", &mut hashes, u64::MAX, 3);
+        dna_hashes(b"ACGTTT", &mut hashes, u64::MAX, 3, true);
         assert_eq!(hashes.len(), 3);
         assert_eq!(hashes.values().sum::<KmerCount>(), 4);
 
@@ -346,4 +460,79 @@ mod tests {
         assert_eq!(hashes.get(&tw_hash64(1)), Some(&1));
         assert_eq!(hashes.get(&tw_hash64(0)), Some(&1));
     }
+
+    #[test]
+    fn test_skip_ambiguous_bases() {
+        // k-mers spanning ambiguous bases should be skipped
+        //
+        // kmer | rev  | smallest | binary    | decimal
+        // ACGT | ACGT | ACGT     | 00011011b | 27
+        // CGTA | TACG | CGTA     | 01101100b | 108
+        // GTAC | GTAC | GTAC     | 10110001b | 177
+
+        let mut hashes = Hashes::default();
+        let num_kmers = dna_hashes(b"ACGTACNNCGTAnACGTRGTAC", &mut hashes, u64::MAX, 4, true);
+
+        assert_eq!(num_kmers, 6);
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes.values().sum::<KmerCount>(), 6);
+
+        assert_eq!(hashes.get(&tw_hash64(27)), Some(&2));
+        assert_eq!(hashes.get(&tw_hash64(108)), Some(&2));
+        assert_eq!(hashes.get(&tw_hash64(177)), Some(&2));
+
+        // no k-mers if ambiguous bases occur more frequently than every k bases
+        let mut hashes = Hashes::default();
+        let num_kmers = dna_hashes(b"ACGNACGNACGNNNN", &mut hashes, u64::MAX, 4, true);
+        assert_eq!(num_kmers, 0);
+        assert!(hashes.is_empty());
+    }
+
+    #[test]
+    fn test_long_kmers() {
+        // k-mers longer than 32 bases are encoded with 128 bits
+        let mut hashes = Hashes::default();
+        let num_kmers = dna_hashes_128(&[b'A'; 40], &mut hashes, u64::MAX, 33, true);
+        assert_eq!(num_kmers, 8);
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes.get(&tw_hash128(0)), Some(&8));
+
+        let mut hashes = Hashes::default();
+        dna_hashes_128(&[b'T'; 40], &mut hashes, u64::MAX, 33, true);
+        assert_eq!(hashes.get(&tw_hash128(0)), Some(&8));
+
+        // C = 01b so CCC...C has all 2*k bits set to 01
+        let ccc = (0..64).fold(0u128, |acc, _| (acc << 2) | 1);
+        let mut hashes = Hashes::default();
+        dna_hashes_128(&[b'C'; 64], &mut hashes, u64::MAX, 64, true);
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes.get(&tw_hash128(ccc)), Some(&1));
+    }
+
+    #[test]
+    fn test_long_kmers_rev_comp() {
+        // a sequence and its reverse complement should produce identical hashes
+        let seq = b"ACGTTGCAAGGCTTACCGATGCATTGACCGTAGGCTAACGTTAGCAATCGGATCCAGTNACGGTACCA";
+        let rev_comp: Vec<u8> = seq.iter().rev()
+            .map(|nt| match nt {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                b'T' => b'A',
+                _ => *nt,
+            })
+            .collect();
+
+        for k in [33, 41, 51] {
+            let mut fwd_hashes = Hashes::default();
+            let fwd_kmers = dna_hashes_128(seq, &mut fwd_hashes, u64::MAX, k, true);
+
+            let mut rev_hashes = Hashes::default();
+            let rev_kmers = dna_hashes_128(&rev_comp, &mut rev_hashes, u64::MAX, k, true);
+
+            assert!(fwd_kmers > 0);
+            assert_eq!(fwd_kmers, rev_kmers);
+            assert_eq!(fwd_hashes, rev_hashes);
+        }
+    }
 }
@@ -0,0 +1,325 @@
+//! This module partitions k-mer diversity across a hierarchy of nested groups of genomes.
+//!
+//! Genomes are assigned to nested groups (e.g. species → clade → lineage) and the k-mers of all genomes
+//! within each group pooled. Following the multiplicative partitioning of Hill numbers of order 1
+//! ([Jost, 2007](https://doi.org/10.1890/06-1736.1)), the diversity of each group (gamma) is the product
+//! of the mean diversity of its members (alpha) and the effective number of distinct members (beta),
+//! with members weighted by their number of k-mers. Applying this at every level decomposes the total
+//! diversity of all genomes into the mean diversity of individual genomes multiplied by a beta
+//! component for each level of the hierarchy. For groups at the lowest level, whose members are
+//! genomes, beta is the K-Hill number of the group.
+//!
+//! Pooled k-mer counts are calculated from the lowest level upwards, so each level only requires the
+//! pooled counts of the level below it.
+
+use std::collections::HashMap;
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rustc_hash::FxHashMap;
+
+use crate::hashing::{count_u64, Hashes, ItemHash};
+
+/// Identifier of the node containing all genomes.
+pub const ROOT_NODE_ID: &str = "root";
+
+/// Separator between the names of nested groups in node identifiers.
+pub const NODE_ID_SEPARATOR: &str = ";";
+
+/// Diversity of a group of genomes within the hierarchy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HierarchyNode {
+    /// Depth of the node, with 0 being the root node containing all genomes
+    pub depth: usize,
+    pub node_id: String,
+    pub parent_id: Option<String>,
+    pub num_children: usize,
+    pub num_genomes: usize,
+    /// Entropy of the pooled k-mers of all genomes in the node
+    pub gamma_entropy: f64,
+    /// Weighted mean entropy of the pooled k-mers of each child of the node
+    pub alpha_entropy: f64,
+}
+
+impl HierarchyNode {
+    /// Effective number of k-mers in the node.
+    pub fn gamma_diversity(&self) -> f64 {
+        self.gamma_entropy.exp()
+    }
+
+    /// Effective number of k-mers within children of the node.
+    pub fn alpha_diversity(&self) -> f64 {
+        self.alpha_entropy.exp()
+    }
+
+    /// Effective number of distinct children of the node.
+    pub fn beta_diversity(&self) -> f64 {
+        (self.gamma_entropy - self.alpha_entropy).exp()
+    }
+}
+
+/// Contribution of a level of the hierarchy to the total diversity of all genomes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelPartition {
+    /// Depth of the level, with 0 being the root and the last level being individual genomes
+    pub depth: usize,
+    pub num_nodes: usize,
+    /// Weighted mean entropy of the pooled k-mers of nodes at this level
+    pub entropy: f64,
+    /// Entropy of the level above less the entropy of this level
+    pub beta_entropy: f64,
+}
+
+impl LevelPartition {
+    /// Effective number of k-mers within nodes at this level.
+    pub fn alpha_diversity(&self) -> f64 {
+        self.entropy.exp()
+    }
+
+    /// Effective number of distinct nodes at this level within each node of the level above.
+    pub fn beta_diversity(&self) -> f64 {
+        self.beta_entropy.exp()
+    }
+}
+
+/// Node being constructed along with the pooled k-mer counts of its genomes.
+struct PooledNode {
+    path: Vec<String>,
+    kmer_counts: FxHashMap<ItemHash, u64>,
+    total_count: u64,
+    entropy: f64,
+    num_children: usize,
+    num_genomes: usize,
+    /// Sum of the k-mer count multiplied by the entropy of each child
+    weighted_child_entropy: f64,
+}
+
+/// Partition diversity across a hierarchy of nested groups of genomes.
+///
+/// Each genome is given with the names of the groups containing it, from the highest to the lowest
+/// level of the hierarchy. Every genome must be assigned to the same number of levels. Nodes are
+/// returned from the root downwards, in the order nodes first appear within each level, followed by
+/// the partitioning of diversity at each level from the root to individual genomes.
+pub fn partition_hierarchy(genomes: &[(Vec<String>, &Hashes)]) -> (Vec<HierarchyNode>, Vec<LevelPartition>) {
+    let num_levels = genomes.first().map_or(0, |(path, _)| path.len());
+    assert!(genomes.iter().all(|(path, _)| path.len() == num_levels), "genomes must be assigned to the same number of levels");
+
+    // entropy of individual genomes
+    let genome_stats: Vec<(u64, f64)> = genomes
+        .par_iter()
+        .map(|(_, hashes)| {
+            let counts = hashes.values().map(|&count| count_u64(count));
+            let total_count = counts.clone().sum::<u64>();
+            (total_count, entropy(total_count, counts))
+        })
+        .collect();
+
+    // pool genomes within nodes at the lowest level of the hierarchy
+    let leaf_members = group_by_prefix(genomes.iter().map(|(path, _)| path.as_slice()), num_levels);
+    let mut level_nodes: Vec<PooledNode> = leaf_members
+        .par_iter()
+        .map(|(path, members)| {
+            let mut kmer_counts = FxHashMap::default();
+            for &idx in members {
+                for (hash, &count) in genomes[idx].1 {
+                    *kmer_counts.entry(*hash).or_insert(0) += count_u64(count);
+                }
+            }
+
+            pooled_node(path.clone(), kmer_counts, members.len(), members.len(), members.iter().map(|&idx| genome_stats[idx]))
+        })
+        .collect();
+
+    let total_count = genome_stats.iter().map(|(count, _)| count).sum::<u64>();
+    let genome_entropy = weighted_entropy(genome_stats.iter().copied(), total_count);
+    let mut level_entropies = vec![(genomes.len(), genome_entropy)];
+    let mut levels = Vec::new();
+
+    // pool nodes within their parent node from the lowest level upwards
+    for depth in (0..num_levels).rev() {
+        level_entropies.push((level_nodes.len(), weighted_entropy(level_nodes.iter().map(|n| (n.total_count, n.entropy)), total_count)));
+
+        let parent_members = group_by_prefix(level_nodes.iter().map(|node| node.path.as_slice()), depth);
+        let parent_nodes: Vec<PooledNode> = parent_members
+            .par_iter()
+            .map(|(path, members)| {
+                let mut kmer_counts = FxHashMap::default();
+                for &idx in members {
+                    for (hash, count) in &level_nodes[idx].kmer_counts {
+                        *kmer_counts.entry(*hash).or_insert(0) += count;
+                    }
+                }
+
+                let num_genomes = members.iter().map(|&idx| level_nodes[idx].num_genomes).sum();
+                let child_stats = members.iter().map(|&idx| (level_nodes[idx].total_count, level_nodes[idx].entropy));
+                pooled_node(path.clone(), kmer_counts, members.len(), num_genomes, child_stats)
+            })
+            .collect();
+
+        levels.push(level_nodes);
+        level_nodes = parent_nodes;
+    }
+
+    // root node containing all genomes
+    level_entropies.push((level_nodes.len(), weighted_entropy(level_nodes.iter().map(|n| (n.total_count, n.entropy)), total_count)));
+    levels.push(level_nodes);
+
+    let nodes = levels
+        .iter()
+        .rev()
+        .enumerate()
+        .flat_map(|(depth, level)| {
+            level.iter().map(move |node| HierarchyNode {
+                depth,
+                node_id: node_id(&node.path),
+                parent_id: (depth > 0).then(|| node_id(&node.path[..depth - 1])),
+                num_children: node.num_children,
+                num_genomes: node.num_genomes,
+                gamma_entropy: node.entropy,
+                alpha_entropy: if node.total_count > 0 { node.weighted_child_entropy / node.total_count as f64 } else { 0.0 },
+            })
+        })
+        .collect();
+
+    // level entropies were determined from individual genomes upwards
+    level_entropies.reverse();
+    let partitions = level_entropies
+        .iter()
+        .enumerate()
+        .map(|(depth, &(num_nodes, entropy))| LevelPartition {
+            depth,
+            num_nodes,
+            entropy,
+            beta_entropy: if depth == 0 { 0.0 } else { level_entropies[depth - 1].1 - entropy },
+        })
+        .collect();
+
+    (nodes, partitions)
+}
+
+/// Create node from the pooled k-mer counts of its genomes and the k-mer count and entropy of its children.
+fn pooled_node(
+    path: Vec<String>,
+    kmer_counts: FxHashMap<ItemHash, u64>,
+    num_children: usize,
+    num_genomes: usize,
+    child_stats: impl Iterator<Item = (u64, f64)>,
+) -> PooledNode {
+    let total_count = kmer_counts.values().sum::<u64>();
+    let entropy = entropy(total_count, kmer_counts.values().copied());
+    let weighted_child_entropy = child_stats.map(|(count, entropy)| count as f64 * entropy).sum();
+
+    PooledNode {
+        path,
+        kmer_counts,
+        total_count,
+        entropy,
+        num_children,
+        num_genomes,
+        weighted_child_entropy,
+    }
+}
+
+/// Group items by the first `depth` elements of their path, in the order groups first appear.
+fn group_by_prefix<'a>(paths: impl Iterator<Item = &'a [String]>, depth: usize) -> Vec<(Vec<String>, Vec<usize>)> {
+    let mut groups: Vec<(Vec<String>, Vec<usize>)> = Vec::new();
+    let mut group_index: HashMap<&[String], usize> = HashMap::new();
+    for (idx, path) in paths.enumerate() {
+        let prefix = &path[..depth];
+        let group_idx = *group_index.entry(prefix).or_insert_with(|| {
+            groups.push((prefix.to_vec(), Vec::new()));
+            groups.len() - 1
+        });
+        groups[group_idx].1.push(idx);
+    }
+
+    groups
+}
+
+/// Shannon entropy of k-mer counts.
+fn entropy(total_count: u64, counts: impl Iterator<Item = u64>) -> f64 {
+    let total = total_count as f64;
+    -counts
+        .map(|count| {
+            let p = count as f64 / total;
+            p * p.ln()
+        })
+        .sum::<f64>()
+}
+
+/// Mean entropy weighted by k-mer count.
+fn weighted_entropy(stats: impl Iterator<Item = (u64, f64)>, total_count: u64) -> f64 {
+    if total_count == 0 {
+        return 0.0;
+    }
+
+    stats.map(|(count, entropy)| count as f64 * entropy).sum::<f64>() / total_count as f64
+}
+
+/// Identifier of a node from the names of the nested groups containing it.
+fn node_id(path: &[String]) -> String {
+    if path.is_empty() {
+        ROOT_NODE_ID.to_string()
+    } else {
+        path.join(NODE_ID_SEPARATOR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::tests::hashes;
+    use crate::khill::khill_number;
+
+    fn path(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_partition_hierarchy() {
+        let genome1 = hashes(&[(1, 2), (2, 1), (3, 1)]);
+        let genome2 = hashes(&[(1, 1), (4, 3)]);
+        let genome3 = hashes(&[(2, 2), (5, 1), (6, 1)]);
+        let genome4 = hashes(&[(7, 1), (8, 1)]);
+        let genomes = vec![
+            (path(&["speciesA", "clade1"]), &genome1),
+            (path(&["speciesA", "clade1"]), &genome2),
+            (path(&["speciesA", "clade2"]), &genome3),
+            (path(&["speciesB", "clade1"]), &genome4),
+        ];
+
+        let (nodes, partitions) = partition_hierarchy(&genomes);
+        let node_ids: Vec<&str> = nodes.iter().map(|node| node.node_id.as_str()).collect();
+        assert_eq!(node_ids, ["root", "speciesA", "speciesB", "speciesA;clade1", "speciesA;clade2", "speciesB;clade1"]);
+        assert_eq!(nodes[0].num_children, 2);
+        assert_eq!(nodes[0].num_genomes, 4);
+        assert_eq!(nodes[0].parent_id, None);
+        assert_eq!(nodes[3].parent_id.as_deref(), Some("speciesA"));
+
+        // beta diversity of the lowest level is the K-Hill number of its genomes
+        assert!((nodes[3].beta_diversity() - khill_number(&[&genome1, &genome2])).abs() < 1e-12);
+        assert!((nodes[5].beta_diversity() - 1.0).abs() < 1e-12);
+
+        // gamma diversity is the product of alpha and beta diversity at each node
+        for node in &nodes {
+            assert!((node.gamma_diversity() - node.alpha_diversity() * node.beta_diversity()).abs() < 1e-9);
+        }
+
+        // total diversity is the product of the mean diversity of genomes and beta diversity of each level
+        assert_eq!(partitions.len(), 4);
+        let num_nodes: Vec<usize> = partitions.iter().map(|level| level.num_nodes).collect();
+        assert_eq!(num_nodes, [1, 2, 3, 4]);
+        assert!((partitions[0].alpha_diversity() - nodes[0].gamma_diversity()).abs() < 1e-12);
+        let product = partitions.last().unwrap().alpha_diversity()
+            * partitions.iter().map(LevelPartition::beta_diversity).product::<f64>();
+        assert!((product - nodes[0].gamma_diversity()).abs() < 1e-9);
+
+        // without nested levels, the root contains the genomes and beta diversity is K-Hill
+        let flat: Vec<(Vec<String>, &Hashes)> = genomes.iter().map(|(_, hashes)| (Vec::new(), *hashes)).collect();
+        let (nodes, partitions) = partition_hierarchy(&flat);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(partitions.len(), 2);
+        let expected = khill_number(&[&genome1, &genome2, &genome3, &genome4]);
+        assert!((nodes[0].beta_diversity() - expected).abs() < 1e-12);
+        assert!((partitions[1].beta_diversity() - expected).abs() < 1e-12);
+    }
+}
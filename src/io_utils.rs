@@ -1,24 +1,203 @@
+//! Utilities for identifying genomic sequence files and their compression format.
+//!
+//! Sequence files may be uncompressed or compressed with gzip, bzip2, xz, or zstd. The compression
+//! format is determined from the file extension when identifying files and from the magic bytes at
+//! the start of a file when reading it, so misnamed files can be reported.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
+use anyhow::{Context, Result};
+use log::warn;
+
+/// Extensions of genomic FASTA files identified in input directories.
+pub const FASTA_EXTENSIONS: [&str; 3] = ["fa", "fasta", "fna"];
+
+/// Extensions of sequence files removed to determine genome identifiers.
+const SEQ_EXTENSIONS: [&str; 5] = ["fq", "fna", "fa", "fasta", "fastq"];
+
+/// Compression format of a sequence file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Determine compression format from file extension.
+    pub fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("bz2") => Compression::Bzip2,
+            Some("xz") => Compression::Xz,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Determine compression format from the magic bytes at the start of a file.
+    pub fn from_magic_bytes(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Determine compression format of a file from its magic bytes.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)
+            .context(format!("Failed to open {}", path.display()))?;
+
+        let mut magic = Vec::with_capacity(6);
+        file.by_ref().take(6).read_to_end(&mut magic)
+            .context(format!("Failed to read {}", path.display()))?;
+
+        Ok(Compression::from_magic_bytes(&magic))
+    }
+}
+
+/// Remove compression extension from file name.
+fn strip_compression_ext(file_name: &str) -> &str {
+    [".gz", ".bz2", ".xz", ".zst"]
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext))
+        .unwrap_or(file_name)
+}
+
+/// Return true if path is a genomic FASTA file, which may be compressed.
+pub fn is_fasta_file(path: &Path) -> bool {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+
+    let file_name = strip_compression_ext(file_name);
+    Path::new(file_name)
+        .extension()
+        .is_some_and(|ext| FASTA_EXTENSIONS.iter().any(|e| ext == *e))
+}
+
 /// Extracts genome identifier from a given sequence file path by removing common file extensions.
 pub fn genome_id_from_filename(seq_file: &Path) -> String {
-    let mut genome_id = seq_file.file_name().unwrap().to_string_lossy().to_string();
+    let file_name = seq_file.file_name().unwrap().to_string_lossy();
+    let file_name = strip_compression_ext(&file_name);
+
+    let genome_id = SEQ_EXTENSIONS
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(&format!(".{ext}")))
+        .unwrap_or(file_name);
+
+    genome_id.to_string()
+}
 
-    if genome_id.ends_with(".gz") {
-        genome_id = genome_id.replace(".gz", "");
+/// Replace genome identifiers shared by different genome files with the full path of each file.
+///
+/// A warning is logged for any shared identifiers, which are also returned in sorted order.
+pub fn disambiguate_genome_ids<'a>(genomes: impl IntoIterator<Item = (&'a Path, &'a mut String)>) -> Vec<String> {
+    let genomes: Vec<(&Path, &mut String)> = genomes.into_iter().collect();
+
+    let mut id_counts: HashMap<String, usize> = HashMap::new();
+    for (_, genome_id) in &genomes {
+        *id_counts.entry(genome_id.to_string()).or_default() += 1;
     }
 
-    if genome_id.ends_with(".fq") {
-        genome_id = genome_id.replace(".fq", "");
-    } else if genome_id.ends_with(".fna") {
-        genome_id = genome_id.replace(".fna", "");
-    } else if genome_id.ends_with(".fa") {
-        genome_id = genome_id.replace(".fa", "");
-    } else if genome_id.ends_with(".fasta") {
-        genome_id = genome_id.replace(".fasta", "");
-    } else if genome_id.ends_with(".fastq") {
-        genome_id = genome_id.replace(".fastq", "");
+    for (genome_file, genome_id) in genomes {
+        if id_counts[genome_id.as_str()] > 1 {
+            *genome_id = genome_file.display().to_string();
+        }
     }
 
-    genome_id
-}
\ No newline at end of file
+    let mut shared_ids: Vec<String> = id_counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(genome_id, _)| genome_id)
+        .collect();
+    shared_ids.sort();
+
+    if !shared_ids.is_empty() {
+        warn!(
+            "Genome identifiers shared by multiple genome files replaced with full paths: {}",
+            shared_ids.join(", ")
+        );
+    }
+
+    shared_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_genome_id_from_filename() {
+        assert_eq!(genome_id_from_filename(Path::new("/data/genome1.fna")), "genome1");
+        assert_eq!(genome_id_from_filename(Path::new("genome1.fa.gz")), "genome1");
+        assert_eq!(genome_id_from_filename(Path::new("genome1.fasta.bz2")), "genome1");
+        assert_eq!(genome_id_from_filename(Path::new("genome1.fna.xz")), "genome1");
+        assert_eq!(genome_id_from_filename(Path::new("genome1.fna.zst")), "genome1");
+        assert_eq!(genome_id_from_filename(Path::new("GCF_000005845.2.fna.gz")), "GCF_000005845.2");
+        assert_eq!(genome_id_from_filename(Path::new("genome1")), "genome1");
+    }
+
+    #[test]
+    fn test_disambiguate_genome_ids() {
+        let files = [
+            PathBuf::from("/data/a/genome1.fna"),
+            PathBuf::from("/data/b/genome1.fna.gz"),
+            PathBuf::from("/data/a/genome2.fna"),
+        ];
+        let mut genome_ids: Vec<String> = files.iter().map(|f| genome_id_from_filename(f)).collect();
+
+        let shared = disambiguate_genome_ids(files.iter().map(PathBuf::as_path).zip(genome_ids.iter_mut()));
+        assert_eq!(shared, ["genome1"]);
+        assert_eq!(genome_ids, ["/data/a/genome1.fna", "/data/b/genome1.fna.gz", "genome2"]);
+    }
+
+    #[test]
+    fn test_is_fasta_file() {
+        assert!(is_fasta_file(Path::new("genome1.fna")));
+        assert!(is_fasta_file(Path::new("genome1.fa.gz")));
+        assert!(is_fasta_file(Path::new("genome1.fasta.bz2")));
+        assert!(is_fasta_file(Path::new("genome1.fna.xz")));
+        assert!(is_fasta_file(Path::new("genome1.fna.zst")));
+        assert!(!is_fasta_file(Path::new("genome1.gz")));
+        assert!(!is_fasta_file(Path::new("genome1.tsv")));
+        assert!(!is_fasta_file(Path::new("genome1.khs")));
+    }
+
+    #[test]
+    fn test_compression() -> Result<()> {
+        assert_eq!(Compression::from_extension(Path::new("genome1.fna")), Compression::None);
+        assert_eq!(Compression::from_extension(Path::new("genome1.fna.gz")), Compression::Gzip);
+        assert_eq!(Compression::from_extension(Path::new("genome1.fna.bz2")), Compression::Bzip2);
+        assert_eq!(Compression::from_extension(Path::new("genome1.fna.xz")), Compression::Xz);
+        assert_eq!(Compression::from_extension(Path::new("genome1.fna.zst")), Compression::Zstd);
+
+        assert_eq!(Compression::from_magic_bytes(b">seq1\nACGT"), Compression::None);
+        assert_eq!(Compression::from_magic_bytes(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(Compression::from_magic_bytes(b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(Compression::from_magic_bytes(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]), Compression::Xz);
+        assert_eq!(Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f, 0xfd]), Compression::Zstd);
+
+        // files shorter than the longest magic number
+        let temp_dir = tempdir()?;
+        let file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&file, ">")?;
+        assert_eq!(Compression::from_file(&file)?, Compression::None);
+
+        Ok(())
+    }
+}
@@ -1,51 +1,256 @@
 //! This module implements the K-Hill method for calculating beta entropy (K-hill number) across a set of genomes.
 //!
 //! It provides functionality to:
-//! - Sketch genome sequences into k-mer hashes in parallel.
+//! - Sketch genome sequences into k-mer hashes in parallel, or load previously computed sketches,
+//!   collecting any genomes that fail to be sketched.
 //! - Aggregate k-mer counts across genomes.
 //! - Compute the K-Hill number and per-genome KL-divergence and weights.
+//! - Compute the weighted alpha entropy and gamma entropy underlying the K-Hill number.
+//! - Compute alpha, gamma, and beta Hill numbers of arbitrary order q.
+//! - Compute the K-Hill number with each genome left out to identify genomes with a large influence.
 //!
-//! The main entry point is the `khill` function, which returns the K-Hill number and detailed entropy components for each genome.
+//! The main entry points are the `khill` function for genome files and the `khill_from_sketches` function for
+//! in-memory sketches, which return a `KHillResult` containing the K-Hill number, alpha and
+//! gamma entropy, detailed entropy components for each genome, and Hill numbers for any requested orders.
 
-use std::fs::File;
-use std::path::PathBuf;
+use std::borrow::Borrow;
+use std::path::{Path, PathBuf};
 use rustc_hash::FxHashMap;
 
-use anyhow::{Context, Result};
-use needletail::parse_fastx_reader;
-use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-use crate::hashing::{ItemHash, Hashes};
+use crate::hashing::{count_u64, ItemHash, Hashes};
+use crate::io_utils::disambiguate_genome_ids;
+use crate::sketch::{is_sketch_file, Sketch};
 use crate::sketch_params::SketchParams;
-use crate::io_utils::genome_id_from_filename;
 
 /// Hill components.
 #[derive(Clone, Debug, PartialEq)]
 pub struct HillComponent {
+    pub entropy: f64,
     pub kl_divergence: f64,
     pub weight: f64,
 }
 
-/// Calculate beta entropy using the K-Hill method.
-pub fn khill(genome_files: &Vec<PathBuf>, sketch_params: &SketchParams) -> Result<(f64, FxHashMap<String, HillComponent>)> {
-    // calculate hashes for all genomes in parallel
-    let genome_hashes: FxHashMap<String, Hashes> = genome_files
+/// Results of the K-Hill method for a set of genomes.
+/// 
+/// The gamma entropy of the pooled k-mer distribution is partitioned into the weighted
+/// alpha entropy of individual genomes and the beta entropy between genomes. The K-Hill
+/// number is the Hill-number transform of the beta entropy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KHillResult {
+    pub alpha_entropy: f64,
+    pub gamma_entropy: f64,
+    pub beta_entropy: f64,
+    pub genome_components: FxHashMap<String, HillComponent>,
+    pub hill_numbers: Vec<HillNumbers>,
+    pub leave_one_out_khill: FxHashMap<String, f64>,
+}
+
+impl KHillResult {
+    /// K-Hill number (effective number of genomes).
+    pub fn khill(&self) -> f64 {
+        self.beta_entropy.exp()
+    }
+
+    /// Effective number of k-mers within a genome.
+    pub fn alpha_diversity(&self) -> f64 {
+        self.alpha_entropy.exp()
+    }
+
+    /// Effective number of k-mers across all genomes.
+    pub fn gamma_diversity(&self) -> f64 {
+        self.gamma_entropy.exp()
+    }
+
+    /// Change in the K-Hill number when a genome is removed from the set of genomes.
+    /// 
+    /// Positive values indicate the genome increases the K-Hill number, as expected for outlier or
+    /// contaminated genomes. The change is NaN if the genome is the only genome in the set.
+    pub fn delta_khill(&self, genome_id: &str) -> Option<f64> {
+        self.leave_one_out_khill.get(genome_id).map(|loo_khill| self.khill() - loo_khill)
+    }
+}
+
+/// Genome that could not be sketched along with the reason for the failure.
+#[derive(Debug)]
+pub struct FailedGenome {
+    pub genome_file: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// Alpha, gamma, and beta Hill numbers of order q.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HillNumbers {
+    pub q: f64,
+    pub alpha: f64,
+    pub gamma: f64,
+    pub beta: f64,
+}
+
+/// Calculate beta entropy using the K-Hill method along with Hill numbers of the specified orders.
+/// 
+/// An error is returned if any genome fails to be sketched.
+pub fn khill(
+    genome_files: &[PathBuf],
+    sketch_params: &SketchParams,
+    orders: &[f64],
+) -> Result<KHillResult> {
+    let (genome_hashes, failed_genomes) = sketch_genomes(genome_files, sketch_params);
+    if let Some(failed) = failed_genomes.into_iter().next() {
+        return Err(failed.error.context(format!("Failed to sketch {}", failed.genome_file.display())));
+    }
+
+    Ok(khill_from_hashes(&genome_hashes, orders))
+}
+
+/// Calculate beta entropy using the K-Hill method from in-memory sketches.
+/// 
+/// Sketches must have been created with identical sketch parameters and have distinct genome identifiers.
+pub fn khill_from_sketches(sketches: &[Sketch], orders: &[f64]) -> Result<KHillResult> {
+    let mut genome_hashes = FxHashMap::default();
+    for sketch in sketches {
+        sketch.params.check_compatibility(&sketches[0].params)
+            .context(format!("Incompatible sketch parameters for {}", sketch.genome_id))?;
+        if genome_hashes.insert(sketch.genome_id.clone(), &sketch.hashes).is_some() {
+            bail!("Genome identifier '{}' is used by multiple sketches.", sketch.genome_id);
+        }
+    }
+
+    Ok(khill_from_hashes(&genome_hashes, orders))
+}
+
+/// Calculate hashes for all genomes in parallel.
+/// 
+/// Genomes which fail to be sketched are returned along with the reason for the failure
+/// so they can be reported together instead of aborting at the first failure.
+/// Different genome files with the same genome identifier are identified by their full path.
+pub fn sketch_genomes(genome_files: &[PathBuf], sketch_params: &SketchParams) -> (FxHashMap<String, Hashes>, Vec<FailedGenome>) {
+    let sketches: Vec<Result<Sketch, FailedGenome>> = genome_files
         .par_iter()
         .map(|genome_file| {
-            let genome_id = genome_id_from_filename(genome_file);
-            let hashes = sketch_file(genome_file, sketch_params).expect("Failed to create sketch");
-            (genome_id, hashes)
+            load_sketch(genome_file, sketch_params).map_err(|error| FailedGenome {
+                genome_file: genome_file.clone(),
+                error,
+            })
+        })
+        .collect();
+
+    let mut sketched = Vec::new();
+    let mut failed_genomes = Vec::new();
+    for (genome_file, sketch) in genome_files.iter().zip(sketches) {
+        match sketch {
+            Ok(sketch) => sketched.push((genome_file, sketch)),
+            Err(failed) => failed_genomes.push(failed),
+        }
+    }
+
+    // the same genome file may be listed more than once, but different files must have distinct identifiers
+    sketched.sort_by(|a, b| a.0.cmp(b.0));
+    sketched.dedup_by(|a, b| a.0 == b.0);
+    disambiguate_genome_ids(
+        sketched
+            .iter_mut()
+            .map(|(genome_file, sketch)| (genome_file.as_path(), &mut sketch.genome_id)),
+    );
+
+    let genome_hashes = sketched
+        .into_iter()
+        .map(|(_genome_file, sketch)| (sketch.genome_id, sketch.hashes))
+        .collect();
+
+    (genome_hashes, failed_genomes)
+}
+
+/// Summarize genomes which failed to be sketched as a single error.
+pub fn failed_genomes_error(failed_genomes: &[FailedGenome]) -> anyhow::Error {
+    let reasons: Vec<String> = failed_genomes
+        .iter()
+        .map(|failed| format!("  {}: {:#}", failed.genome_file.display(), failed.error))
+        .collect();
+
+    anyhow!("Failed to sketch {} genomes:\n{}", failed_genomes.len(), reasons.join("\n"))
+}
+
+/// Calculate beta entropy using the K-Hill method from previously calculated genome hashes.
+/// 
+/// Hashes may be owned or shared (e.g. `Arc<Hashes>` from a sketch cache).
+pub fn khill_from_hashes<H: Borrow<Hashes> + Sync>(genome_hashes: &FxHashMap<String, H>, orders: &[f64]) -> KHillResult {
+    // determine k-mers across all genomes
+    let all_kmers = kmer_counts(genome_hashes.par_iter().map(|(_genome_id, hashes)| hashes.borrow()));
+
+    // calculate the K-hill number in parallel
+    let total_num_hashes: u64 = all_kmers.values().sum();
+    
+    let genome_results: FxHashMap<String, HillComponent> = genome_hashes
+        .par_iter()
+        .map(|(genome_id, hashes)| {
+            (genome_id.clone(), hill_component(hashes.borrow(), &all_kmers, total_num_hashes))
+        })
+        .collect();
+
+    // calculate final K-hill value
+    let beta_entropy = genome_results.values()
+        .map(|comp| comp.weight * comp.kl_divergence)
+        .sum::<f64>();
+
+    // calculate weighted alpha entropy and gamma entropy of pooled k-mers
+    let alpha_entropy = genome_results.values()
+        .map(|comp| comp.weight * comp.entropy)
+        .sum::<f64>();
+
+    let gamma_entropy = -all_kmers.values()
+        .map(|&count| {
+            let p_i = count as f64 / total_num_hashes as f64;
+            p_i * p_i.ln()
+        })
+        .sum::<f64>();
+
+    let hill_numbers = orders.iter()
+        .map(|&q| hill_numbers(q, genome_hashes, &all_kmers, total_num_hashes))
+        .collect();
+
+    let leave_one_out_khill = leave_one_out_khill(genome_hashes, &all_kmers, total_num_hashes, alpha_entropy);
+
+    KHillResult {
+        alpha_entropy,
+        gamma_entropy,
+        beta_entropy,
+        genome_components: genome_results,
+        hill_numbers,
+        leave_one_out_khill,
+    }
+}
+
+/// Calculate the K-Hill number for a collection of genomes.
+/// 
+/// The collection may contain the same genome multiple times (e.g. when resampling genomes
+/// with replacement), in which case each occurrence is treated as a separate genome.
+pub fn khill_number(genome_hashes: &[&Hashes]) -> f64 {
+    let all_kmers = kmer_counts(genome_hashes.par_iter().copied());
+    let total_num_hashes: u64 = all_kmers.values().sum();
+
+    // weighted KL-divergences are summed in a fixed order so results are reproducible
+    let weighted_kl_divergences: Vec<f64> = genome_hashes
+        .par_iter()
+        .map(|hashes| {
+            let comp = hill_component(hashes, &all_kmers, total_num_hashes);
+            comp.weight * comp.kl_divergence
         })
         .collect();
 
-    // determine k-mers across all genomes in parallel using map-reduce
-    let all_kmers = genome_hashes.values()
-        .par_bridge()
+    weighted_kl_divergences.iter().sum::<f64>().exp()
+}
+
+/// Determine total count of each k-mer across genomes in parallel using map-reduce.
+pub fn kmer_counts<'a>(genome_hashes: impl ParallelIterator<Item = &'a Hashes>) -> FxHashMap<ItemHash, u64> {
+    genome_hashes
         .map(|hashes| {
             // Create a local HashMap for each thread
             let mut local_map = FxHashMap::<ItemHash, u64>::default();
             for (hash, count) in hashes {
-                *local_map.entry(*hash).or_insert(0) += *count as u64;
+                *local_map.entry(*hash).or_insert(0) += count_u64(*count);
             }
             local_map
         })
@@ -59,57 +264,169 @@ pub fn khill(genome_files: &Vec<PathBuf>, sketch_params: &SketchParams) -> Resul
                 }
                 acc
             }
-        );
+        )
+}
 
-    // calculate the K-hill number in parallel
-    let total_num_hashes: u64 = all_kmers.values().sum();
+/// Calculate entropy, KL-divergence, and weight of a genome relative to the pooled k-mers of all genomes.
+fn hill_component(hashes: &Hashes, all_kmers: &FxHashMap<ItemHash, u64>, total_num_hashes: u64) -> HillComponent {
+    let num_genome_hashes: u64 = hashes.values().map(|&v| count_u64(v)).sum();
+
+    let entropy = -hashes.values()
+        .map(|&count| {
+            let p_si = count as f64 / num_genome_hashes as f64;
+            p_si * p_si.ln()
+        })
+        .sum::<f64>();
     
-    let genome_results: FxHashMap<String, HillComponent> = genome_hashes
+    let kl_divergence = hashes.iter()
+        .filter_map(|(hash, count)| {
+            all_kmers.get(hash).map(|total_count| {
+                let p_si = *count as f64 / num_genome_hashes as f64;
+                let p_i = *total_count as f64 / total_num_hashes as f64;
+                p_si * (p_si/p_i).ln()
+            })
+        })
+        .sum();
+
+    let weight = num_genome_hashes as f64 / total_num_hashes as f64;
+    HillComponent { entropy, kl_divergence, weight }
+}
+
+/// Calculate the K-Hill number with each genome left out of the set of genomes.
+/// 
+/// The beta entropy equals ln(N) - S/N - A/N, where N is the total count of all k-mers,
+/// S = Σ t_i ln(t_i) over the total count t_i of each k-mer, and A = Σ N_j H_j over the
+/// count N_j and entropy H_j of each genome. Removing a genome only changes the terms of S
+/// for k-mers in the genome, so the pooled k-mer counts are updated rather than recalculated
+/// and each genome is processed in time proportional to the size of its sketch.
+fn leave_one_out_khill<H: Borrow<Hashes> + Sync>(
+    genome_hashes: &FxHashMap<String, H>,
+    all_kmers: &FxHashMap<ItemHash, u64>,
+    total_num_hashes: u64,
+    alpha_entropy: f64,
+) -> FxHashMap<String, f64> {
+    let x_ln_x = |x: f64| if x > 0.0 { x * x.ln() } else { 0.0 };
+
+    let total = total_num_hashes as f64;
+    let s = all_kmers.values().map(|&count| x_ln_x(count as f64)).sum::<f64>();
+    let a = total * alpha_entropy;
+
+    genome_hashes
         .par_iter()
         .map(|(genome_id, hashes)| {
-            let num_genome_hashes: u64 = hashes.values().map(|&v| v as u64).sum();
-            
-            let kl_divergence = hashes.iter()
-                .filter_map(|(hash, count)| {
-                    all_kmers.get(hash).map(|total_count| {
-                        let p_si = *count as f64 / num_genome_hashes as f64;
-                        let p_i = *total_count as f64 / total_num_hashes as f64;
-                        p_si * (p_si/p_i).ln()
-                    })
+            let hashes: &Hashes = hashes.borrow();
+            let num_genome_hashes: u64 = hashes.values().map(|&v| count_u64(v)).sum();
+            let remaining = (total_num_hashes - num_genome_hashes) as f64;
+            if remaining == 0.0 {
+                return (genome_id.clone(), f64::NAN);
+            }
+
+            let s_genome = hashes.iter()
+                .map(|(hash, &count)| {
+                    let total_count = all_kmers[hash] as f64;
+                    x_ln_x(total_count) - x_ln_x(total_count - count as f64)
                 })
-                .sum();
+                .sum::<f64>();
+            let a_genome = x_ln_x(num_genome_hashes as f64) - hashes.values().map(|&count| x_ln_x(count as f64)).sum::<f64>();
+
+            let beta_entropy = remaining.ln() - (s - s_genome) / remaining - (a - a_genome) / remaining;
+            (genome_id.clone(), beta_entropy.exp())
+        })
+        .collect()
+}
 
+/// Calculate alpha, gamma, and beta Hill numbers of order q.
+/// 
+/// Alpha diversity is calculated following Jost (2007) with genomes weighted by their
+/// number of hashes, so gamma diversity is the product of alpha and beta diversity and
+/// the beta Hill number of order 1 equals the K-Hill number.
+fn hill_numbers<H: Borrow<Hashes> + Sync>(
+    q: f64,
+    genome_hashes: &FxHashMap<String, H>,
+    all_kmers: &FxHashMap<ItemHash, u64>,
+    total_num_hashes: u64,
+) -> HillNumbers {
+    let gamma = hill_number(q, all_kmers.values().map(|&count| count as f64 / total_num_hashes as f64));
+
+    // determine genome weight and sum of relative k-mer abundances raised to the power q
+    // (or entropy for q = 1 and the maximum relative abundance for q = infinity)
+    let genome_terms: Vec<(f64, f64)> = genome_hashes
+        .par_iter()
+        .map(|(_genome_id, hashes)| {
+            let hashes: &Hashes = hashes.borrow();
+            let num_genome_hashes: u64 = hashes.values().map(|&v| count_u64(v)).sum();
             let weight = num_genome_hashes as f64 / total_num_hashes as f64;
-            (genome_id.clone(), HillComponent { kl_divergence, weight })
+            let p_si = hashes.values().map(|&count| count as f64 / num_genome_hashes as f64);
+
+            let term = if q == 1.0 {
+                -p_si.map(|p| p * p.ln()).sum::<f64>()
+            } else if q.is_infinite() {
+                p_si.fold(0.0, f64::max)
+            } else {
+                p_si.map(|p| p.powf(q)).sum::<f64>()
+            };
+
+            (weight, term)
         })
         .collect();
 
-    // calculate final K-hill value
-    let khill = genome_results.values()
-        .map(|comp| comp.weight * comp.kl_divergence)
-        .sum::<f64>();
+    let alpha = if q == 1.0 {
+        genome_terms.iter()
+            .map(|(weight, entropy)| weight * entropy)
+            .sum::<f64>()
+            .exp()
+    } else if q.is_infinite() {
+        let max_weight = genome_terms.iter().map(|(weight, _)| *weight).fold(0.0, f64::max);
+        let max_weighted_p = genome_terms.iter().map(|(weight, max_p)| weight * max_p).fold(0.0, f64::max);
+        max_weight / max_weighted_p
+    } else {
+        let weight_sum = genome_terms.iter().map(|(weight, _)| weight.powf(q)).sum::<f64>();
+        let weighted_sum = genome_terms.iter().map(|(weight, sum_p)| weight.powf(q) * sum_p).sum::<f64>();
+        (weighted_sum / weight_sum).powf(1.0 / (1.0 - q))
+    };
 
-    Ok((khill.exp(), genome_results))
+    HillNumbers { q, alpha, gamma, beta: gamma / alpha }
+}
+
+/// Calculate the Hill number of order q for a set of relative abundances.
+fn hill_number(q: f64, rel_abundances: impl Iterator<Item = f64>) -> f64 {
+    if q == 1.0 {
+        (-rel_abundances.map(|p| p * p.ln()).sum::<f64>()).exp()
+    } else if q.is_infinite() {
+        1.0 / rel_abundances.fold(0.0, f64::max)
+    } else {
+        rel_abundances.map(|p| p.powf(q)).sum::<f64>().powf(1.0 / (1.0 - q))
+    }
 }
 
 /// Create sketch from sequence file.
-pub fn sketch_file(seq_file: &PathBuf, sketch_params: &SketchParams) -> Result<Hashes> {
-    let mut sketcher = sketch_params.create_sketcher();
-    let reader = File::open(seq_file)
-        .context(format!("Failed to open {}", seq_file.display()))?;
+pub fn sketch_file(seq_file: &Path, sketch_params: &SketchParams) -> Result<Hashes> {
+    Ok(Sketch::from_seq_file(seq_file, sketch_params)?.hashes)
+}
 
-    let mut fastx_reader = parse_fastx_reader(reader)?;
-    while let Some(rec) = fastx_reader.next() {
-        let record = rec?;
-        sketcher.process_seq(&record);
-    }
+/// Load sketch from a sketch file or create it from a sequence file.
+/// 
+/// Sketches read from file must have been created with compatible sketch parameters, except that
+/// weighted sketches are converted into unweighted sketches if unweighted sketches are requested.
+pub fn load_sketch(genome_file: &Path, sketch_params: &SketchParams) -> Result<Sketch> {
+    if is_sketch_file(genome_file) {
+        let mut sketch = Sketch::read(genome_file)?;
+        if sketch.params.weighted() && !sketch_params.weighted() {
+            sketch = sketch.to_unweighted();
+        }
 
-    Ok(sketcher.to_hashes())
+        sketch.params.check_compatibility(sketch_params)
+            .context(format!("Incompatible sketch parameters in {}", genome_file.display()))?;
+        Ok(sketch)
+    } else {
+        Sketch::from_seq_file(genome_file, sketch_params)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
     use std::io::Write;
     use tempfile::{tempdir, TempDir};
 
@@ -134,13 +451,15 @@ mod tests {
         let file2 = write_temp_fasta(fasta2, "genome2.fa", &temp_dir);
 
         // Use default sketch params
-        let sketch_params = SketchParams::new(3, 1, true);
+        let sketch_params = SketchParams::new(3, 1, true, true);
 
         let genome_files = vec![file1.clone(), file2.clone()];
-        let result = khill(&genome_files, &sketch_params);
+        let result = khill(&genome_files, &sketch_params, &[]);
 
         assert!(result.is_ok());
-        let (khill_value, genome_entropy) = result.unwrap();
+        let result = result.unwrap();
+        let khill_value = result.khill();
+        let genome_entropy = &result.genome_components;
 
         // Check for expected K-hill value
         assert!(khill_value == 1.0376237334557157);
@@ -156,4 +475,180 @@ mod tests {
 
         // temp_dir is automatically cleaned up when it goes out of scope
     }
+
+    #[test]
+    fn test_khill_with_sketch_files() {
+        let temp_dir = tempdir().unwrap();
+
+        let file1 = write_temp_fasta(">seq1\nACGTACGTACGT\n", "genome1.fa", &temp_dir);
+        let file2 = write_temp_fasta(">seq2\nACGTACGTACGA\n", "genome2.fa", &temp_dir);
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+
+        // write sketches and calculate K-hill from the sketch files
+        let mut sketch_files = Vec::new();
+        for file in [&file1, &file2] {
+            let sketch = Sketch::from_seq_file(file, &sketch_params).unwrap();
+            let sketch_file = sketch.path_in_dir(temp_dir.path());
+            sketch.write(&sketch_file).unwrap();
+            sketch_files.push(sketch_file);
+        }
+
+        let result_fasta = khill(&[file1.clone(), file2.clone()], &sketch_params, &[]).unwrap();
+        let result_sketch = khill(&sketch_files, &sketch_params, &[]).unwrap();
+        assert_eq!(result_fasta, result_sketch);
+        assert!(result_sketch.genome_components.contains_key("genome1"));
+        assert!(result_sketch.genome_components.contains_key("genome2"));
+
+        // sketches must have been created with compatible parameters
+        let other_params = SketchParams::new(4, 1, true, true);
+        assert!(load_sketch(&sketch_files[0], &other_params).is_err());
+
+        // weighted sketches can be used to calculate unweighted K-Hill, but not the reverse
+        let unweighted_params = SketchParams::new(3, 1, false, true);
+        let result_unweighted = khill(&sketch_files, &unweighted_params, &[]).unwrap();
+        assert_eq!(result_unweighted, khill(&[file1, file2], &unweighted_params, &[]).unwrap());
+        assert_ne!(result_unweighted.khill(), result_sketch.khill());
+
+        let unweighted_sketch = load_sketch(&sketch_files[0], &unweighted_params).unwrap();
+        unweighted_sketch.write(&sketch_files[0]).unwrap();
+        assert!(load_sketch(&sketch_files[0], &sketch_params).is_err());
+    }
+
+    #[test]
+    fn test_khill_from_sketches() {
+        let temp_dir = tempdir().unwrap();
+
+        let file1 = write_temp_fasta(">seq1\nACGTACGTACGT\n", "genome1.fa", &temp_dir);
+        let file2 = write_temp_fasta(">seq2\nACGTACGTACGA\n", "genome2.fa", &temp_dir);
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let sketches = [
+            Sketch::from_seqs("genome1", [b"ACGTACGTACGT"], &sketch_params),
+            Sketch::from_seqs("genome2", [b"ACGTACGTACGA"], &sketch_params),
+        ];
+        let result = khill_from_sketches(&sketches, &[1.0]).unwrap();
+        assert_eq!(result, khill(&[file1, file2], &sketch_params, &[1.0]).unwrap());
+
+        // genome identifiers must be unique and sketch parameters identical
+        let duplicate = Sketch::from_seqs("genome1", [b"ACGTTTGA"], &sketch_params);
+        assert!(khill_from_sketches(&[sketches[0].clone(), duplicate], &[]).is_err());
+        let other = Sketch::from_seqs("genome3", [b"ACGTTTGA"], &SketchParams::new(4, 1, true, true));
+        assert!(khill_from_sketches(&[sketches[0].clone(), other], &[]).is_err());
+    }
+
+    #[test]
+    fn test_hill_numbers() {
+        let temp_dir = tempdir().unwrap();
+
+        let file1 = write_temp_fasta(">seq1\nACGTACGTACGT\n", "genome1.fa", &temp_dir);
+        let file2 = write_temp_fasta(">seq2\nACGTACGTACGA\n", "genome2.fa", &temp_dir);
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let orders = [0.0, 0.5, 1.0, 2.0, f64::INFINITY];
+        let result = khill(&[file1, file2], &sketch_params, &orders).unwrap();
+        let hill_numbers = &result.hill_numbers;
+
+        assert_eq!(hill_numbers.len(), orders.len());
+        for (hill, q) in hill_numbers.iter().zip(orders) {
+            assert_eq!(hill.q, q);
+            assert!((hill.beta - hill.gamma / hill.alpha).abs() < 1e-12);
+            assert!(hill.beta >= 1.0 && hill.beta <= 2.0);
+        }
+
+        // genome 1 has the canonical 3-mers {ACG, GTA} and genome 2 also contains CGA
+        // so there are 3 distinct k-mers across the genomes with a mean richness of 2.5
+        assert_eq!(hill_numbers[0].gamma, 3.0);
+        assert_eq!(hill_numbers[0].alpha, 2.5);
+
+        // the beta Hill number of order 1 is the K-Hill number
+        assert!((hill_numbers[2].beta - result.khill()).abs() < 1e-12);
+
+        // Hill numbers decrease with increasing order
+        for pair in hill_numbers.windows(2) {
+            assert!(pair[0].gamma >= pair[1].gamma);
+            assert!(pair[0].alpha >= pair[1].alpha);
+        }
+    }
+
+    #[test]
+    fn test_entropy_partition() {
+        let temp_dir = tempdir().unwrap();
+
+        let file1 = write_temp_fasta(">seq1\nACGTACGTACGT\n", "genome1.fa", &temp_dir);
+        let file2 = write_temp_fasta(">seq2\nACGTACGTACGA\n", "genome2.fa", &temp_dir);
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let result = khill(&[file1, file2], &sketch_params, &[1.0]).unwrap();
+
+        // gamma entropy is partitioned into alpha and beta entropy
+        assert!((result.gamma_entropy - result.alpha_entropy - result.beta_entropy).abs() < 1e-12);
+        assert!(result.alpha_entropy > 0.0);
+        assert!(result.gamma_entropy >= result.alpha_entropy);
+
+        // Hill-number transforms agree with Hill numbers of order 1
+        let hill = &result.hill_numbers[0];
+        assert!((result.alpha_diversity() - hill.alpha).abs() < 1e-12);
+        assert!((result.gamma_diversity() - hill.gamma).abs() < 1e-12);
+        assert!((result.khill() - hill.beta).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_leave_one_out_khill() {
+        let genome_hashes: FxHashMap<String, Hashes> = [
+            ("genome1", vec![(1, 2), (2, 1), (3, 1)]),
+            ("genome2", vec![(1, 1), (4, 3)]),
+            ("genome3", vec![(2, 2), (5, 1), (6, 1)]),
+        ]
+        .into_iter()
+        .map(|(genome_id, counts)| (genome_id.to_string(), counts.into_iter().collect()))
+        .collect();
+
+        let result = khill_from_hashes(&genome_hashes, &[]);
+        assert_eq!(result.leave_one_out_khill.len(), 3);
+
+        // agrees with recalculating K-Hill without each genome
+        for genome_id in genome_hashes.keys() {
+            let others: Vec<&Hashes> = genome_hashes.iter()
+                .filter(|(other_id, _)| *other_id != genome_id)
+                .map(|(_, hashes)| hashes)
+                .collect();
+            let expected = khill_number(&others);
+            assert!((result.leave_one_out_khill[genome_id] - expected).abs() < 1e-12);
+            assert!((result.delta_khill(genome_id).unwrap() - (result.khill() - expected)).abs() < 1e-12);
+        }
+
+        // K-Hill is undefined once the only genome is removed
+        let single: FxHashMap<String, Hashes> = genome_hashes.into_iter().take(1).collect();
+        let result = khill_from_hashes(&single, &[]);
+        assert!(result.leave_one_out_khill.values().all(|khill| khill.is_nan()));
+    }
+
+    #[test]
+    fn test_failed_genomes() {
+        let temp_dir = tempdir().unwrap();
+
+        let file1 = write_temp_fasta(">seq1\nACGTACGTACGT\n", "genome1.fa", &temp_dir);
+        let file2 = write_temp_fasta(">seq2\nACGTACGTACGA\n", "genome2.fa", &temp_dir);
+        let truncated = write_temp_fasta("ACGT", "truncated.fa", &temp_dir);
+        let missing = temp_dir.path().join("missing.fa");
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let genome_files = vec![file1.clone(), truncated.clone(), file2.clone(), missing.clone()];
+        let (genome_hashes, failed_genomes) = sketch_genomes(&genome_files, &sketch_params);
+
+        assert_eq!(genome_hashes.len(), 2);
+        assert_eq!(failed_genomes.len(), 2);
+        assert_eq!(failed_genomes[0].genome_file, truncated);
+        assert_eq!(failed_genomes[1].genome_file, missing);
+
+        let summary = failed_genomes_error(&failed_genomes).to_string();
+        assert!(summary.contains("Failed to sketch 2 genomes"));
+        assert!(summary.contains("truncated.fa"));
+        assert!(summary.contains("missing.fa"));
+
+        // calculating K-Hill directly from files fails if any genome can't be sketched
+        assert!(khill(&genome_files, &sketch_params, &[]).is_err());
+        assert!(khill(&[file1, file2], &sketch_params, &[]).is_ok());
+    }
 }
@@ -0,0 +1,100 @@
+//! Library for calculating the effective number of genomes (K-Hill number) in a collection of genomes.
+//!
+//! Genomes are sketched into FracMinHash k-mer hashes, either from sequence files or from in-memory
+//! sequences, and the pooled k-mer distribution of a set of genomes is partitioned into alpha, gamma,
+//! and beta entropy. The K-Hill number is the Hill-number transform of the beta entropy. The items
+//! re-exported at the crate root form the stable public API:
+//!
+//! - Sketching: `SketchParams`, `Sketch`, `sketch_file`, and `sketch_genomes`.
+//! - Aggregation: `kmer_counts` across the hashes of a set of genomes.
+//! - K-Hill computation: `khill`, `khill_from_sketches`, and `khill_from_hashes`.
+//! - Result types: `KHillResult`, `HillComponent`, `HillNumbers`, and `FailedGenome`.
+//! - Incremental sketching: the `FracMinHash` sketcher returned by `SketchParams::create_sketcher`.
+//!
+//! Parallel functions run on the rayon thread pool they are called from, which is the global pool
+//! unless called within `ThreadPool::install`. The `*_with_pool` functions run on a caller-provided
+//! thread pool so the library never needs to configure the global pool.
+//!
+//! ```
+//! use khill::{khill_from_sketches_with_pool, Sketch, SketchParams};
+//!
+//! let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+//! let sketch_params = SketchParams::new(3, 1, true, true);
+//! let sketches = [
+//!     Sketch::from_seqs("genome1", [b"ACGTACGTACGT"], &sketch_params),
+//!     Sketch::from_seqs("genome2", [b"GGATCCAAGTTT"], &sketch_params),
+//! ];
+//!
+//! let result = khill_from_sketches_with_pool(&pool, &sketches, &[]).unwrap();
+//! assert!(result.khill() > 1.0 && result.khill() <= 2.0);
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use rayon::ThreadPool;
+use rustc_hash::FxHashMap;
+
+pub mod khill;
+pub mod sketch_params;
+pub mod sketch;
+pub mod frac_min_hash;
+pub mod hashing;
+
+// Modules supporting the khill command-line interface, which are not part of the stable API.
+#[doc(hidden)]
+pub mod progress;
+mod bootstrap;
+mod rarefaction;
+#[doc(hidden)]
+pub mod sketch_cache;
+#[doc(hidden)]
+pub mod genome_groups;
+#[doc(hidden)]
+pub mod distance;
+#[doc(hidden)]
+pub mod permutation;
+#[doc(hidden)]
+pub mod hierarchy;
+#[doc(hidden)]
+pub mod group_analysis;
+#[doc(hidden)]
+pub mod output;
+#[cfg(feature = "database")]
+#[doc(hidden)]
+pub mod database;
+#[doc(hidden)]
+pub mod io_utils;
+
+pub use crate::hashing::{Hashes, ItemHash, KmerCount};
+pub use crate::khill::{
+    khill, khill_from_hashes, khill_from_sketches, kmer_counts, sketch_file, sketch_genomes, FailedGenome,
+    HillComponent, HillNumbers, KHillResult,
+};
+pub use crate::frac_min_hash::FracMinHash;
+pub use crate::sketch::Sketch;
+pub use crate::sketch_params::SketchParams;
+
+/// Calculate the K-Hill number of genome files using the provided thread pool.
+pub fn khill_with_pool(
+    pool: &ThreadPool,
+    genome_files: &[PathBuf],
+    sketch_params: &SketchParams,
+    orders: &[f64],
+) -> Result<KHillResult> {
+    pool.install(|| khill(genome_files, sketch_params, orders))
+}
+
+/// Calculate the K-Hill number of in-memory sketches using the provided thread pool.
+pub fn khill_from_sketches_with_pool(pool: &ThreadPool, sketches: &[Sketch], orders: &[f64]) -> Result<KHillResult> {
+    pool.install(|| khill_from_sketches(sketches, orders))
+}
+
+/// Sketch genome files using the provided thread pool.
+pub fn sketch_genomes_with_pool(
+    pool: &ThreadPool,
+    genome_files: &[PathBuf],
+    sketch_params: &SketchParams,
+) -> (FxHashMap<String, Hashes>, Vec<FailedGenome>) {
+    pool.install(|| sketch_genomes(genome_files, sketch_params))
+}
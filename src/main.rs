@@ -1,36 +1,45 @@
 
 //! Main entry point for the khill application.
 //!
-//! This file handles command-line parsing, logging setup, input validation, and orchestrates
-//! the computation of k-hill statistics and genome entropy for groups of genomic FASTA files.
-//! It supports input via a directory of FASTA files or a TSV file specifying genome groups.
-//! Results are written to output files in the specified directory.
+//! This file is a thin wrapper around the khill library. It handles command-line parsing, logging setup,
+//! input validation, and orchestrates the computation of k-hill statistics and genome entropy for groups
+//! of genomic FASTA files. It supports input via a directory of FASTA files or a TSV file specifying genome groups.
+//! Genomes can also be sketched ahead of time with the `sketch` command and the resulting
+//! sketch files used in place of FASTA files. Results are written to output files in the
+//! specified directory.
 
 use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use std::collections::HashMap;
-use anyhow::{Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use log::info;
+use itertools::Itertools;
+use log::{info, warn};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-use crate::cli::Cli;
+use khill::distance::{pairwise_distances, PairwiseDistance, SIMILARITY_MEASURES};
+use khill::genome_groups::GenomeGroups;
+use khill::hashing::Hashes;
+use khill::hierarchy::{partition_hierarchy, NODE_ID_SEPARATOR, ROOT_NODE_ID};
+use khill::group_analysis::{analyze_groups, sort_groups, AnalysisParams};
+use khill::io_utils::{genome_id_from_filename, is_fasta_file};
+use khill::output::{GroupResults, Results, RunParameters};
+use khill::permutation::compare_groups;
+use khill::khill::{failed_genomes_error, FailedGenome};
+use khill::progress::progress_bar;
+use khill::sketch::{is_sketch_file, Sketch};
+use khill::sketch_cache::SketchCache;
+use khill::sketch_params::SketchParams;
+
+use crate::cli::{Cli, Command, CompareArgs, DistArgs, DistLayout, HierarchyArgs, KhillArgs, OnError, SketchArgs};
 use crate::logging::setup_logger;
-use crate::khill::khill;
-use crate::progress::progress_bar;
-use crate::sketch_params::SketchParams;
 
 mod cli;
-pub mod logging;
-pub mod progress;
-pub mod khill;
-pub mod sketch_params;
-pub mod frac_min_hash;
-pub mod hashing;
-pub mod io_utils;
+mod logging;
 
 /// Common initialization required by all commands.
 fn init(threads: usize) -> Result<()> {
@@ -46,112 +55,302 @@ fn init(threads: usize) -> Result<()> {
     Ok(())
 }
 
-/// Parse a TSV file containing genome groups and the path to their genomic FASTA files.
-fn parse_genome_groups_file(file_path: &PathBuf) -> Result<HashMap<String, Vec<PathBuf>>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    
-    // process each lines
-    let mut groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
-    for line in reader.lines() {
-        let line = line?;
+/// Determine groups of genomes specified via a genome group table or an input directory.
+/// 
+/// All files in the input directory identified as genome files are placed in a single group.
+fn genome_groups(
+    input_dir: Option<PathBuf>,
+    genome_group_table: Option<PathBuf>,
+    is_genome_file: impl Fn(&Path) -> bool,
+) -> Result<GenomeGroups> {
+    if let Some(genome_group_table) = genome_group_table {
+        info!("Using genome group file: {}", genome_group_table.display());
+        GenomeGroups::from_table(&genome_group_table)
+    } else if let Some(input_dir) = input_dir {
+        info!("Using input directory: {}", input_dir.display());
+        GenomeGroups::from_dir(&input_dir, is_genome_file)
+    } else {
+        Err(anyhow::anyhow!("No input specified. Use --input_dir or --genome_group_table."))
+    }
+}
 
-        // skip comment lines starting with #
-        if line.starts_with('#') || line.trim().is_empty() {
-            continue;
+/// Verify that all genome files exist.
+fn verify_genome_files(groups: &GenomeGroups) -> Result<()> {
+    info!("Verifying all genomic FASTA files exist.");
+    let num_genomes = groups.groups().iter().map(|(_, v)| v.len()).sum::<usize>();
+    let progress_bar = progress_bar(num_genomes as u64);
+    for (group, genome_paths) in groups.groups() {
+        for path in genome_paths {
+            if !path.exists() {
+                return Err(anyhow::anyhow!("Genome file {} in group '{}' does not exist.", path.display(), group));
+            }
+            progress_bar.inc(1);
         }
+    }
+    progress_bar.finish();
 
-        let fields: Vec<&str> = line.trim().split('\t').collect();
-        if fields.len() != 2 {
-            return Err(anyhow::anyhow!("Invalid TSV format: each line must have exactly 2 columns (group_id, path)"));
+    Ok(())
+}
+
+/// Sketch each unique genome across all groups, handling genomes that fail to be sketched as specified.
+fn build_sketch_cache(
+    groups: &GenomeGroups,
+    sketch_params: &SketchParams,
+    max_cache_memory: Option<u64>,
+    on_error: OnError,
+    out_dir: &Path,
+) -> Result<SketchCache> {
+    let genome_files = groups.genome_files();
+    let memory_budget = max_cache_memory.map(|mb| mb * 1024 * 1024);
+    info!("Sketch parameters: {}.", sketch_params);
+    info!("Sketching {} unique genomes:", genome_files.len());
+    let (sketch_cache, failed_genomes) = SketchCache::build(&genome_files, groups.genome_ids(), sketch_params, memory_budget, out_dir)?;
+    if sketch_cache.spilled_count() > 0 {
+        info!(
+            "Holding {} sketches in memory ({:.1} MB) and {} sketches on disk.",
+            sketch_cache.len() - sketch_cache.spilled_count(),
+            sketch_cache.in_memory_bytes() as f64 / (1024.0 * 1024.0),
+            sketch_cache.spilled_count()
+        );
+    }
+
+    if !failed_genomes.is_empty() {
+        if on_error == OnError::Fail {
+            return Err(failed_genomes_error(&failed_genomes));
         }
-        
-        let group = fields[0].to_string();
-        let path = PathBuf::from(fields[1]);
 
-        groups.entry(group)
-            .or_default()
-            .push(path);
+        warn!("Excluding {} genomes that failed to be sketched; see failed_genomes.tsv.", failed_genomes.len());
+        write_failed_genomes(out_dir, &failed_genomes)?;
     }
-    
-    Ok(groups)
+
+    Ok(sketch_cache)
 }
 
-fn main() -> Result<()> {
-    let start = Instant::now();
+/// Calculate K-Hill for each group of genomes.
+fn run_khill(args: KhillArgs) -> Result<()> {
+    setup_logger(&args.out_dir)?;
 
-    let args = Cli::parse();
+    init(args.sketching.threads)?;
+    let sketch_params = args.sketching.sketch_params()?;
 
-    setup_logger(&args.out_dir)?;
+    // each order is reported once, so repeated orders are rejected before any output is written
+    check_unique_hill_orders(&args.hill_orders)?;
 
-    init(args.threads)?;
+    #[cfg(not(feature = "database"))]
+    if args.database.is_some() {
+        bail!("Writing results to a database requires khill to be built with the database feature.");
+    }
 
     // determine if input is being specified via a directory or a file table
+    let groups = genome_groups(
+        args.input_dir,
+        args.genome_group_table,
+        |path| is_fasta_file(path) || is_sketch_file(path),
+    )?;
 
-    let groups = if let Some(genome_group_table) = args.genome_group_table {
-        info!("Using genome group file: {}", genome_group_table.display());
-        parse_genome_groups_file(&genome_group_table)?
-    } else if let Some(input_dir) = args.input_dir {
-        info!("Using input directory: {}", input_dir.display());
+    // check that all genomic FASTA files exist
+    if !args.cache.skip_file_check {
+        verify_genome_files(&groups)?;
+    }
 
-        // If a directory is specified, can it for FASTA files.
-        let paths: Vec<PathBuf> = std::fs::read_dir(input_dir)?
-            .filter_map(Result::ok)
-            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "fa" || ext == "fasta" || ext == "fna"))
-            .map(|entry| entry.path())
-            .collect();
+    std::fs::create_dir_all(&args.out_dir)?;
 
-        if paths.is_empty() {
-            return Err(anyhow::anyhow!("No FASTA files found in specified directory."));
-        }
-        
-        let mut groups = HashMap::new();
-        groups.insert("default".to_string(), paths);
-        groups
+    // sketch each genome once, even if it is in multiple groups
+    let sketch_cache = build_sketch_cache(&groups, &sketch_params, args.cache.max_cache_memory, args.sketching.on_error, &args.out_dir)?;
+
+    let mut bootstrap_writer = if args.bootstrap > 0 {
+        let bootstrap_out_file = File::create(args.out_dir.join("khill_bootstrap.tsv"))?;
+        let mut bootstrap_writer = BufWriter::new(bootstrap_out_file);
+        writeln!(bootstrap_writer, "group_id\tnum_genomes\tk-hill\treplicates\tmean\tstd_err\tci_lower\tci_upper")?;
+        Some(bootstrap_writer)
     } else {
-        return Err(anyhow::anyhow!("No input specified. Use --input_dir or --genome_group_table."));
+        None
     };
 
-    // check that all genomic FASTA files exist
-    if !args.skip_file_check {
-        info!("Verifying all genomic FASTA files exist.");
-        let num_genomes = groups.values().map(|v| v.len()).sum::<usize>();
-        let progress_bar = progress_bar(num_genomes as u64);
-        for (group, genome_paths) in &groups {
-            for path in genome_paths {
-                if !path.exists() {
-                    return Err(anyhow::anyhow!("Genome file {} in group '{}' does not exist.", path.display(), group));
-                }
-                progress_bar.inc(1);
+    let mut rarefaction_writer = if args.rarefaction_step > 0 {
+        let rarefaction_out_file = File::create(args.out_dir.join("khill_rarefaction.tsv"))?;
+        let mut rarefaction_writer = BufWriter::new(rarefaction_out_file);
+        writeln!(rarefaction_writer, "group_id\tnum_genomes\treplicate\tk-hill")?;
+        Some(rarefaction_writer)
+    } else {
+        None
+    };
+
+    // process groups of genomes concurrently, writing results in a consistent group order
+    info!("Processing {} genome groups:", groups.len());
+    let analysis_params = AnalysisParams {
+        hill_orders: args.hill_orders.clone(),
+        bootstrap_replicates: args.bootstrap,
+        confidence: args.confidence,
+        rarefaction_step: args.rarefaction_step,
+        rarefaction_replicates: args.rarefaction_replicates,
+        seed: args.seed,
+    };
+    let group_genomes: Vec<(&String, &Vec<PathBuf>)> = groups.groups().iter().map(|(group, paths)| (group, paths)).collect();
+    let progress_bar = progress_bar(group_genomes.len() as u64);
+    let mut results = analyze_groups(&group_genomes, &sketch_cache, &analysis_params, &progress_bar)?;
+    sort_groups(&mut results, args.sort_by.into());
+
+    for analysis in &results {
+        let result = &analysis.khill;
+        if let (Some(bootstrap_writer), Some(summary)) = (bootstrap_writer.as_mut(), &analysis.bootstrap) {
+            writeln!(bootstrap_writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                analysis.group_id,
+                analysis.num_genomes,
+                result.khill(),
+                summary.replicates,
+                summary.mean,
+                summary.std_err,
+                summary.ci_lower,
+                summary.ci_upper)?;
+        }
+
+        if let (Some(rarefaction_writer), Some(curve)) = (rarefaction_writer.as_mut(), &analysis.rarefaction) {
+            for point in curve {
+                writeln!(rarefaction_writer, "{}\t{}\t{}\t{}", analysis.group_id, point.num_genomes, point.replicate, point.khill)?;
             }
         }
-        progress_bar.finish();
     }
 
-    // open output file for group k-hill and per genome entropy results
+    progress_bar.finish();
+
+    // write group and per-genome results in the requested format
+    let results = Results {
+        parameters: RunParameters::new(&sketch_params, &analysis_params),
+        metadata_columns: groups.metadata_columns().to_vec(),
+        groups: results.iter()
+            .map(|analysis| GroupResults::new(analysis, &groups, args.sort_by.into()))
+            .collect(),
+    };
+    results.write(&args.out_dir, args.output_format.into())?;
+
+    #[cfg(feature = "database")]
+    if let Some(db_path) = &args.database {
+        let run_id = khill::database::write_results(db_path, &results, &env::args().collect::<Vec<String>>().join(" "))?;
+        info!("Appended results to {} as run {}.", db_path.display(), run_id);
+    }
+
+    Ok(())
+}
+
+/// Create sketch file for each genome.
+fn run_sketch(args: SketchArgs) -> Result<()> {
+    setup_logger(&args.out_dir)?;
+
+    init(args.sketching.threads)?;
+    let sketch_params = args.sketching.sketch_params()?;
+
+    let groups = genome_groups(args.input_dir, args.genome_group_table, is_fasta_file)?;
+    verify_genome_files(&groups)?;
+
+    // sketch each genome once, even if it is in multiple groups
+    let genome_files = groups.genome_files();
+    check_unique_genome_ids(&groups)?;
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    info!("Sketch parameters: {}.", sketch_params);
+    info!("Sketching {} genomes:", genome_files.len());
+    let progress_bar = progress_bar(genome_files.len() as u64);
+    let failed_genomes: Vec<FailedGenome> = genome_files
+        .par_iter()
+        .filter_map(|genome_file| {
+            let result = Sketch::from_seq_file(genome_file, &sketch_params)
+                .and_then(|mut sketch| {
+                    if let Some(genome_id) = groups.genome_id(genome_file) {
+                        sketch.genome_id = genome_id.to_string();
+                    }
+                    sketch.write(&sketch.path_in_dir(&args.out_dir))
+                });
+            progress_bar.inc(1);
+
+            result.err().map(|error| FailedGenome {
+                genome_file: genome_file.to_path_buf(),
+                error,
+            })
+        })
+        .collect();
+
+    progress_bar.finish();
+
+    if !failed_genomes.is_empty() {
+        if args.sketching.on_error == OnError::Fail {
+            return Err(failed_genomes_error(&failed_genomes));
+        }
+
+        warn!("Failed to sketch {} genomes; see failed_genomes.tsv.", failed_genomes.len());
+        write_failed_genomes(&args.out_dir, &failed_genomes)?;
+    }
+
+    Ok(())
+}
+
+/// Calculate pairwise similarity between genomes in each group.
+fn run_dist(args: DistArgs) -> Result<()> {
+    setup_logger(&args.out_dir)?;
+
+    init(args.sketching.threads)?;
+    let sketch_params = args.sketching.sketch_params()?;
+
+    let groups = genome_groups(
+        args.input_dir,
+        args.genome_group_table,
+        |path| is_fasta_file(path) || is_sketch_file(path),
+    )?;
+
+    if !args.cache.skip_file_check {
+        verify_genome_files(&groups)?;
+    }
+
     std::fs::create_dir_all(&args.out_dir)?;
-    let khill_out_file = File::create(args.out_dir.join("khill.tsv"))?;
-    let mut khill_writer = BufWriter::new(khill_out_file);
-    writeln!(khill_writer, "group_id\tnum_genomes\tk-hill")?;
 
-    let genome_entropy_out_file = File::create(args.out_dir.join("genome_entropy.tsv"))?;
-    let mut genome_entropy_writer = BufWriter::new(genome_entropy_out_file);
-    writeln!(genome_entropy_writer, "genome_id\tbeta_entropy\tkl_divergence\tweight")?;
+    let sketch_cache = build_sketch_cache(&groups, &sketch_params, args.cache.max_cache_memory, args.sketching.on_error, &args.out_dir)?;
 
-    // process each group of genomes
-    let sketch_params = SketchParams::new(args.kmer_length, args.scale, true);
-    info!("Processing {} genome groups:", groups.len());
+    let mut sparse_writer = if args.layout == DistLayout::Sparse {
+        let dist_out_file = File::create(args.out_dir.join("dist.tsv"))?;
+        let mut sparse_writer = BufWriter::new(dist_out_file);
+        writeln!(sparse_writer, "group_id\tgenome_id_a\tgenome_id_b\tjaccard\tweighted_jaccard\tcontainment_a\tcontainment_b\tani")?;
+        Some(sparse_writer)
+    } else {
+        None
+    };
+
+    info!("Calculating pairwise similarity for {} genome groups:", groups.len());
     let progress_bar = progress_bar(groups.len() as u64);
-    for (group, genome_paths) in &groups {
-        let (k_hill, genome_stats) = khill(genome_paths, &sketch_params)?;
+    let mut matrix_file_names = HashMap::new();
+    for (group, genome_paths) in groups.groups() {
+        let genomes = sketch_cache.cached_genomes(genome_paths);
+        let genome_hashes = sketch_cache.genome_hashes(genome_paths)?;
+        let hashes: Vec<&Hashes> = genomes.iter()
+            .map(|(genome_id, _)| genome_hashes[genome_id].as_ref())
+            .collect();
+        let dists = pairwise_distances(&hashes, sketch_params.k());
 
-        writeln!(khill_writer, "{}\t{}\t{}", group, genome_paths.len(), k_hill)?;
-        for (genome_id, components) in genome_stats {
-            writeln!(genome_entropy_writer, "{}\t{}\t{}\t{}", 
-            genome_id, 
-            components.weight * components.kl_divergence,
-            components.kl_divergence, 
-            components.weight)?;
+        if let Some(sparse_writer) = sparse_writer.as_mut() {
+            for (i, j, dist) in dists.iter().filter(|(_, _, dist)| dist.ani >= args.min_ani) {
+                writeln!(sparse_writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    group,
+                    genomes[*i].0,
+                    genomes[*j].0,
+                    dist.jaccard,
+                    dist.weighted_jaccard,
+                    dist.containment_a,
+                    dist.containment_b,
+                    dist.ani)?;
+            }
+        } else {
+            // group identifiers may contain characters that are not valid in file names
+            let file_stem: String = group.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+                .collect();
+            if let Some(other_group) = matrix_file_names.insert(file_stem.clone(), group) {
+                bail!("Groups '{}' and '{}' would both be written to files named {}.*.tsv.", other_group, group, file_stem);
+            }
+
+            let genome_ids: Vec<&String> = genomes.iter().map(|(genome_id, _)| genome_id).collect();
+            for (measure, value) in SIMILARITY_MEASURES {
+                let matrix_file = args.out_dir.join(format!("{}.{}.tsv", file_stem, measure));
+                write_dist_matrix(&matrix_file, &genome_ids, &dists, args.layout, value)?;
+            }
         }
 
         progress_bar.inc(1);
@@ -159,6 +358,355 @@ fn main() -> Result<()> {
 
     progress_bar.finish();
 
+    Ok(())
+}
+
+/// Write a similarity measure for all pairs of genomes as a full or lower-triangular matrix.
+///
+/// Entries are given for the genome of each row relative to the genome of each column, which matters
+/// only for asymmetric measures such as containment. Genomes have a similarity of 1 with themselves.
+fn write_dist_matrix(
+    matrix_file: &Path,
+    genome_ids: &[&String],
+    dists: &[(usize, usize, PairwiseDistance)],
+    layout: DistLayout,
+    value: impl Fn(&PairwiseDistance, bool) -> f64,
+) -> Result<()> {
+    let n = genome_ids.len();
+    let mut matrix = vec![vec![1.0; n]; n];
+    for (i, j, dist) in dists {
+        matrix[*i][*j] = value(dist, false);
+        matrix[*j][*i] = value(dist, true);
+    }
+
+    let mut writer = BufWriter::new(File::create(matrix_file)?);
+    writeln!(writer, "genome_id\t{}", genome_ids.iter().join("\t"))?;
+    for (i, genome_id) in genome_ids.iter().enumerate() {
+        let num_columns = match layout {
+            DistLayout::LowerTriangular => i,
+            _ => n,
+        };
+        let values = matrix[i][..num_columns].iter()
+            .map(|v| format!("\t{v}"))
+            .join("");
+        writeln!(writer, "{}{}", genome_id, values)?;
+    }
+
+    Ok(())
+}
+
+/// Test for differences in K-Hill between pairs of groups.
+fn run_compare(args: CompareArgs) -> Result<()> {
+    setup_logger(&args.out_dir)?;
+
+    init(args.sketching.threads)?;
+    let sketch_params = args.sketching.sketch_params()?;
+
+    let groups = genome_groups(
+        None,
+        Some(args.genome_group_table),
+        |path| is_fasta_file(path) || is_sketch_file(path),
+    )?;
+
+    let group_pairs = match &args.group_pairs {
+        Some(group_pairs_file) => parse_group_pairs(group_pairs_file)?,
+        None => groups.groups().iter()
+            .map(|(group, _)| group.clone())
+            .tuple_combinations()
+            .collect(),
+    };
+
+    let group_genomes: HashMap<&String, &Vec<PathBuf>> = groups.groups().iter()
+        .map(|(group, paths)| (group, paths))
+        .collect();
+    for group in group_pairs.iter().flat_map(|(group_a, group_b)| [group_a, group_b]) {
+        if !group_genomes.contains_key(group) {
+            bail!("Group '{}' is not in the genome group table.", group);
+        }
+    }
+
+    if !args.cache.skip_file_check {
+        verify_genome_files(&groups)?;
+    }
+
+    std::fs::create_dir_all(&args.out_dir)?;
+
+    let sketch_cache = build_sketch_cache(&groups, &sketch_params, args.cache.max_cache_memory, args.sketching.on_error, &args.out_dir)?;
+
+    let comparison_out_file = File::create(args.out_dir.join("khill_comparison.tsv"))?;
+    let mut comparison_writer = BufWriter::new(comparison_out_file);
+    writeln!(comparison_writer, "group_id_a\tgroup_id_b\tnum_genomes_a\tnum_genomes_b\tk-hill_a\tk-hill_b\tdifference\tpermutations\tp_value")?;
+
+    info!("Comparing {} pairs of groups:", group_pairs.len());
+    let progress_bar = progress_bar(group_pairs.len() as u64);
+    for (group_a, group_b) in &group_pairs {
+        let genome_paths_a = group_genomes[group_a];
+        let genome_paths_b = group_genomes[group_b];
+        let genome_hashes_a = sketch_cache.genome_hashes(genome_paths_a)?;
+        let genome_hashes_b = sketch_cache.genome_hashes(genome_paths_b)?;
+        if genome_hashes_a.is_empty() || genome_hashes_b.is_empty() {
+            warn!("Skipping comparison of groups '{}' and '{}' as a group has no genomes that could be sketched.", group_a, group_b);
+            progress_bar.inc(1);
+            continue;
+        }
+
+        // genomes are kept in input order so permutations are reproducible
+        let hashes_a: Vec<&Hashes> = sketch_cache.cached_genomes(genome_paths_a).iter()
+            .map(|(genome_id, _)| genome_hashes_a[genome_id].as_ref())
+            .collect();
+        let hashes_b: Vec<&Hashes> = sketch_cache.cached_genomes(genome_paths_b).iter()
+            .map(|(genome_id, _)| genome_hashes_b[genome_id].as_ref())
+            .collect();
+
+        let comparison = compare_groups(&hashes_a, &hashes_b, args.permutations, args.seed);
+        writeln!(comparison_writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            group_a,
+            group_b,
+            hashes_a.len(),
+            hashes_b.len(),
+            comparison.khill_a,
+            comparison.khill_b,
+            comparison.difference,
+            comparison.permutations,
+            comparison.p_value)?;
+
+        progress_bar.inc(1);
+    }
+
+    progress_bar.finish();
+
+    Ok(())
+}
+
+/// Partition diversity across a hierarchy of nested groups.
+fn run_hierarchy(args: HierarchyArgs) -> Result<()> {
+    setup_logger(&args.out_dir)?;
+
+    init(args.sketching.threads)?;
+    let sketch_params = args.sketching.sketch_params()?;
+
+    let groups = genome_groups(
+        None,
+        Some(args.genome_group_table),
+        |path| is_fasta_file(path) || is_sketch_file(path),
+    )?;
+
+    for level in &args.levels {
+        if !groups.metadata_columns().contains(level) {
+            bail!("Column '{}' is not in the header of the genome group table.", level);
+        }
+    }
+
+    // verify nested groups of all genomes before sketching
+    let hierarchy_paths = genome_hierarchy_paths(&groups, &args.levels)?;
+
+    if !args.cache.skip_file_check {
+        verify_genome_files(&groups)?;
+    }
+
+    std::fs::create_dir_all(&args.out_dir)?;
+
+    let sketch_cache = build_sketch_cache(&groups, &sketch_params, args.cache.max_cache_memory, args.sketching.on_error, &args.out_dir)?;
+
+    // determine nested groups containing each genome, starting with the group in the group_id column
+    let mut genome_hashes = Vec::new();
+    for (_, genome_paths) in groups.groups() {
+        let group_hashes = sketch_cache.genome_hashes(genome_paths)?;
+        for (genome_id, genome_file) in sketch_cache.cached_genomes(genome_paths) {
+            let path = hierarchy_paths[genome_file.as_path()].clone();
+            genome_hashes.push((path, group_hashes[&genome_id].clone()));
+        }
+    }
+
+    let genomes: Vec<(Vec<String>, &Hashes)> = genome_hashes.iter()
+        .map(|(path, hashes)| (path.clone(), hashes.as_ref()))
+        .collect();
+    info!("Partitioning diversity of {} genomes across {} levels.", genomes.len(), args.levels.len() + 1);
+    let (nodes, partitions) = partition_hierarchy(&genomes);
+
+    let level_names: Vec<&str> = [ROOT_NODE_ID, "group_id"].into_iter()
+        .chain(args.levels.iter().map(String::as_str))
+        .chain(["genome"])
+        .collect();
+
+    let nodes_out_file = File::create(args.out_dir.join("khill_hierarchy.tsv"))?;
+    let mut nodes_writer = BufWriter::new(nodes_out_file);
+    writeln!(nodes_writer, "level\tnode_id\tparent_id\tnum_children\tnum_genomes\tgamma_diversity\talpha_diversity\tbeta_diversity")?;
+    for node in &nodes {
+        writeln!(nodes_writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            level_names[node.depth],
+            node.node_id,
+            node.parent_id.as_deref().unwrap_or_default(),
+            node.num_children,
+            node.num_genomes,
+            node.gamma_diversity(),
+            node.alpha_diversity(),
+            node.beta_diversity())?;
+    }
+
+    let levels_out_file = File::create(args.out_dir.join("khill_hierarchy_levels.tsv"))?;
+    let mut levels_writer = BufWriter::new(levels_out_file);
+    writeln!(levels_writer, "level\tnum_nodes\talpha_diversity\tbeta_diversity")?;
+    for partition in &partitions {
+        writeln!(levels_writer, "{}\t{}\t{}\t{}",
+            level_names[partition.depth],
+            partition.num_nodes,
+            partition.alpha_diversity(),
+            partition.beta_diversity())?;
+    }
+
+    Ok(())
+}
+
+/// Nested groups containing each genome file.
+///
+/// Each genome must be in a single path of the hierarchy since a genome listed under multiple paths
+/// would be counted once for each path in the diversity of higher levels.
+fn genome_hierarchy_paths<'a>(groups: &'a GenomeGroups, levels: &[String]) -> Result<HashMap<&'a Path, Vec<String>>> {
+    let mut hierarchy_paths: HashMap<&Path, Vec<String>> = HashMap::new();
+    for (group, genome_paths) in groups.groups() {
+        for genome_file in genome_paths {
+            let path = hierarchy_path(groups, group, genome_file, levels)?;
+            match hierarchy_paths.get(genome_file.as_path()) {
+                Some(other_path) if *other_path != path => {
+                    let genome_id = groups.genome_id(genome_file)
+                        .map(str::to_string)
+                        .unwrap_or_else(|| genome_id_from_filename(genome_file));
+                    bail!(
+                        "Genome {} ({}) is listed under both '{}' and '{}' in the hierarchy.",
+                        genome_id,
+                        genome_file.display(),
+                        other_path.join(NODE_ID_SEPARATOR),
+                        path.join(NODE_ID_SEPARATOR)
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    hierarchy_paths.insert(genome_file, path);
+                }
+            }
+        }
+    }
+
+    Ok(hierarchy_paths)
+}
+
+/// Names of the nested groups containing a genome, starting with the group in the group_id column.
+///
+/// Every genome must have a value in each level column, and names may not contain the separator used in
+/// node identifiers as different hierarchies would otherwise produce the same node identifiers.
+fn hierarchy_path(groups: &GenomeGroups, group: &str, genome_file: &Path, levels: &[String]) -> Result<Vec<String>> {
+    if group.contains(NODE_ID_SEPARATOR) {
+        bail!("Group '{}' contains '{}', which is used to separate nested groups.", group, NODE_ID_SEPARATOR);
+    }
+
+    let mut path = vec![group.to_string()];
+    for level in levels {
+        let name = groups.metadata_value(group, genome_file, level).unwrap_or_default();
+        if name.is_empty() {
+            bail!("Genome file {} in group '{}' has no value in column '{}'.", genome_file.display(), group, level);
+        }
+        if name.contains(NODE_ID_SEPARATOR) {
+            bail!(
+                "Genome file {} in group '{}' has the value '{}' in column '{}', which contains '{}' used to separate nested groups.",
+                genome_file.display(),
+                group,
+                name,
+                level,
+                NODE_ID_SEPARATOR
+            );
+        }
+        path.push(name.to_string());
+    }
+
+    Ok(path)
+}
+
+/// Parse a TSV file containing pairs of groups to compare.
+fn parse_group_pairs(file_path: &Path) -> Result<Vec<(String, String)>> {
+    let file = File::open(file_path)
+        .context(format!("Failed to open {}", file_path.display()))?;
+
+    let mut group_pairs = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+
+        // skip comment lines starting with #
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.trim().split('\t').collect();
+        if fields.len() != 2 {
+            bail!("Invalid TSV format: each line must have exactly 2 columns (group_id_a, group_id_b)");
+        }
+
+        group_pairs.push((fields[0].to_string(), fields[1].to_string()));
+    }
+
+    Ok(group_pairs)
+}
+
+/// Write genomes that failed to be sketched, along with the reason for each failure, to failed_genomes.tsv.
+fn write_failed_genomes(out_dir: &Path, failed_genomes: &[FailedGenome]) -> Result<()> {
+    let failed_genomes_out_file = File::create(out_dir.join("failed_genomes.tsv"))?;
+    let mut failed_genomes_writer = BufWriter::new(failed_genomes_out_file);
+    writeln!(failed_genomes_writer, "genome_file\terror")?;
+    for failed in failed_genomes {
+        let error = format!("{:#}", failed.error).replace(['\t', '\n'], " ");
+        writeln!(failed_genomes_writer, "{}\t{}", failed.genome_file.display(), error)?;
+    }
+
+    Ok(())
+}
+
+/// Verify that genome files have distinct genome identifiers so sketch files are not overwritten.
+fn check_unique_genome_ids(groups: &GenomeGroups) -> Result<()> {
+    let mut genome_ids: HashMap<String, &Path> = HashMap::new();
+    for genome_file in groups.genome_files() {
+        let genome_id = groups.genome_id(genome_file)
+            .map(str::to_string)
+            .unwrap_or_else(|| genome_id_from_filename(genome_file));
+        if let Some(other_file) = genome_ids.insert(genome_id.clone(), genome_file) {
+            bail!(
+                "Genome files {} and {} both have the genome identifier '{}'.",
+                other_file.display(),
+                genome_file.display(),
+                genome_id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify that no order of Hill numbers is specified more than once.
+fn check_unique_hill_orders(hill_orders: &[f64]) -> Result<()> {
+    for (idx, q) in hill_orders.iter().enumerate() {
+        if hill_orders[..idx].contains(q) {
+            bail!("Hill number order {} was specified more than once.", q);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let start = Instant::now();
+
+    let args = Cli::parse();
+
+    match args.command {
+        Some(Command::Sketch(sketch_args)) => run_sketch(sketch_args)?,
+        Some(Command::Dist(dist_args)) => run_dist(dist_args)?,
+        Some(Command::Compare(compare_args)) => run_compare(compare_args)?,
+        Some(Command::Hierarchy(hierarchy_args)) => run_hierarchy(hierarchy_args)?,
+        None => match args.khill {
+            Some(khill_args) => run_khill(khill_args)?,
+            None => bail!("No input specified. Use --input_dir or --genome_group_table."),
+        },
+    }
+
     info!("Elapsed time (sec): {:.2}", start.elapsed().as_secs_f32());
     info!("Done.");
 
@@ -170,24 +718,78 @@ mod tests {
     use super::*;
     use std::fs::write;
     use tempfile::NamedTempFile;
-    
+
+    #[test]
+    fn test_parse_group_pairs() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        write(temp_file.path(), "# group_id_a\tgroup_id_b\nclinical\tenvironmental\n\nclinical\tfood\n")?;
+
+        let group_pairs = parse_group_pairs(temp_file.path())?;
+        assert_eq!(group_pairs, [
+            ("clinical".to_string(), "environmental".to_string()),
+            ("clinical".to_string(), "food".to_string()),
+        ]);
+
+        write(temp_file.path(), "clinical\tenvironmental\tfood\n")?;
+        assert!(parse_group_pairs(temp_file.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hierarchy_path() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        write(temp_file.path(), "group_id\tgenome_file\tspecies\n\
+            cladeA\tgenome1.fna\tspeciesA\n\
+            cladeA\tgenome2.fna\t\n\
+            cladeA\tgenome3.fna\tspecies;B\n\
+            clade;B\tgenome4.fna\tspeciesC\n")?;
+        let groups = GenomeGroups::from_table(temp_file.path())?;
+        let levels = ["species".to_string()];
+
+        assert_eq!(hierarchy_path(&groups, "cladeA", Path::new("genome1.fna"), &levels)?, ["cladeA", "speciesA"]);
+        assert_eq!(hierarchy_path(&groups, "cladeA", Path::new("genome1.fna"), &[])?, ["cladeA"]);
+
+        // missing values and names containing the node identifier separator
+        assert!(hierarchy_path(&groups, "cladeA", Path::new("genome2.fna"), &levels).is_err());
+        assert!(hierarchy_path(&groups, "cladeA", Path::new("genome3.fna"), &levels).is_err());
+        assert!(hierarchy_path(&groups, "clade;B", Path::new("genome4.fna"), &levels).is_err());
+
+        Ok(())
+    }
+
     #[test]
-    fn test_parse_genome_groups_file() -> Result<()> {
-        // Create a temporary test file
+    fn test_genome_hierarchy_paths() -> Result<()> {
         let temp_file = NamedTempFile::new()?;
-        let test_content = "# group_id\tpath\n\
-                           group1\t/path/to/genome1.fna\n\
-                           group1\t/path/to/genome2.fna\n\
-                           group2\t/path/to/genome3.fna";
-        write(temp_file.path(), test_content)?;
-        
-        let groups = parse_genome_groups_file(&temp_file.path().to_path_buf())?;
-        
-        assert_eq!(groups.len(), 2);
-        assert_eq!(groups["group1"].len(), 2);
-        assert_eq!(groups["group2"].len(), 1);
-        assert_eq!(groups["group1"][0], PathBuf::from("/path/to/genome1.fna"));
-        
+        write(temp_file.path(), "group_id\tgenome_file\tspecies\n\
+            cladeA\tgenome1.fna\tspeciesA\n\
+            cladeA\tgenome2.fna\tspeciesB\n\
+            cladeB\tgenome3.fna\tspeciesC\n")?;
+        let groups = GenomeGroups::from_table(temp_file.path())?;
+        let levels = ["species".to_string()];
+
+        let hierarchy_paths = genome_hierarchy_paths(&groups, &levels)?;
+        assert_eq!(hierarchy_paths.len(), 3);
+        assert_eq!(hierarchy_paths[Path::new("genome2.fna")], ["cladeA", "speciesB"]);
+        assert_eq!(hierarchy_paths[Path::new("genome3.fna")], ["cladeB", "speciesC"]);
+
+        // genome listed under multiple paths of the hierarchy
+        write(temp_file.path(), "group_id\tgenome_file\tspecies\n\
+            cladeA\tgenome1.fna\tspeciesA\n\
+            cladeB\tgenome1.fna\tspeciesC\n")?;
+        let groups = GenomeGroups::from_table(temp_file.path())?;
+        let err = genome_hierarchy_paths(&groups, &levels).unwrap_err().to_string();
+        assert!(err.contains("genome1 (genome1.fna)"));
+        assert!(err.contains("'cladeA;speciesA' and 'cladeB;speciesC'"));
+
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_check_unique_hill_orders() {
+        assert!(check_unique_hill_orders(&[]).is_ok());
+        assert!(check_unique_hill_orders(&[0.0, 1.0, 2.0, f64::INFINITY]).is_ok());
+        assert!(check_unique_hill_orders(&[1.0, 2.0, 1.0]).is_err());
+        assert!(check_unique_hill_orders(&[0.0, -0.0]).is_err());
+    }
+}
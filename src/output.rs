@@ -0,0 +1,531 @@
+//! This module writes K-Hill results for groups of genomes and the genomes within them.
+//!
+//! Results can be written as tab-separated files (`khill.tsv` and `genome_entropy.tsv`), as a single JSON
+//! document (`khill.json`) with the results of each genome nested within its group, or as Parquet tables
+//! (`khill.parquet` and `genome_entropy.parquet`). JSON and Parquet output also record the parameters of
+//! the run, with Parquet tables storing each parameter in the key-value metadata of the file. All formats
+//! contain the same values, with Hill numbers of each requested order reported as `alpha_q<q>`,
+//! `gamma_q<q>`, and `beta_q<q>` columns in tabular formats. Parquet output requires the `parquet` feature,
+//! which is enabled by default.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+#[cfg(feature = "parquet")]
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+#[cfg(feature = "parquet")]
+use arrow_schema::{DataType, Field, Schema};
+use itertools::Itertools;
+#[cfg(feature = "parquet")]
+use parquet::arrow::ArrowWriter;
+#[cfg(feature = "parquet")]
+use parquet::basic::Compression;
+#[cfg(feature = "parquet")]
+use parquet::file::metadata::KeyValue;
+#[cfg(feature = "parquet")]
+use parquet::file::properties::WriterProperties;
+use serde::ser::SerializeSeq;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::genome_groups::GenomeGroups;
+use crate::group_analysis::{AnalysisParams, GroupAnalysis, SortOrder};
+use crate::sketch_params::SketchParams;
+
+/// Names of columns in tabular genome results, which are followed by any metadata columns.
+pub const GENOME_COLUMNS: [&str; 8] = ["group_id", "genome_id", "beta_entropy", "kl_divergence", "weight", "entropy",
+    "khill_without_genome", "delta_khill"];
+
+/// Format of group and per-genome result files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Tab-separated values
+    Tsv,
+    /// JSON document with run parameters
+    Json,
+    /// Parquet tables with run parameters as key-value metadata
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Parameters of a run recorded with results.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunParameters {
+    pub version: String,
+    pub kmer_length: u8,
+    pub scale: u64,
+    pub kmer_weighting: String,
+    pub skip_ambiguous: bool,
+    #[serde(serialize_with = "serialize_hill_orders")]
+    pub hill_orders: Vec<f64>,
+    pub bootstrap_replicates: usize,
+    pub confidence: f64,
+    pub rarefaction_step: usize,
+    pub rarefaction_replicates: usize,
+    pub seed: u64,
+}
+
+impl RunParameters {
+    pub fn new(sketch_params: &SketchParams, analysis_params: &AnalysisParams) -> Self {
+        RunParameters {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            kmer_length: sketch_params.k(),
+            scale: sketch_params.scale(),
+            kmer_weighting: sketch_params.weighting().to_string(),
+            skip_ambiguous: sketch_params.skip_ambiguous(),
+            hill_orders: analysis_params.hill_orders.clone(),
+            bootstrap_replicates: analysis_params.bootstrap_replicates,
+            confidence: analysis_params.confidence,
+            rarefaction_step: analysis_params.rarefaction_step,
+            rarefaction_replicates: analysis_params.rarefaction_replicates,
+            seed: analysis_params.seed,
+        }
+    }
+}
+
+/// Alpha, gamma, and beta Hill numbers of order q for a group.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HillNumbersRecord {
+    #[serde(serialize_with = "serialize_hill_order")]
+    pub q: f64,
+    pub alpha: f64,
+    pub gamma: f64,
+    pub beta: f64,
+}
+
+/// K-Hill results for a group of genomes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GroupRecord {
+    pub group_id: String,
+    pub num_genomes: usize,
+    #[serde(rename = "k-hill")]
+    pub khill: f64,
+    pub alpha_entropy: f64,
+    pub gamma_entropy: f64,
+    pub beta_entropy: f64,
+    pub alpha_diversity: f64,
+    pub gamma_diversity: f64,
+    pub hill_numbers: Vec<HillNumbersRecord>,
+}
+
+/// Contribution of a genome to the K-Hill number of a group.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GenomeRecord {
+    pub group_id: String,
+    pub genome_id: String,
+    pub beta_entropy: f64,
+    pub kl_divergence: f64,
+    pub weight: f64,
+    pub entropy: f64,
+    pub khill_without_genome: f64,
+    pub delta_khill: f64,
+    /// Values of metadata columns in the genome group table, if specified for the genome
+    #[serde(skip)]
+    pub metadata: Vec<Option<String>>,
+}
+
+/// Results for a group and the genomes within it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupResults {
+    pub group: GroupRecord,
+    pub genomes: Vec<GenomeRecord>,
+}
+
+impl GroupResults {
+    /// Results of a group with genomes in the specified output order.
+    pub fn new(analysis: &GroupAnalysis, groups: &GenomeGroups, sort_by: SortOrder) -> Self {
+        let result = &analysis.khill;
+        let group = GroupRecord {
+            group_id: analysis.group_id.clone(),
+            num_genomes: analysis.num_genomes,
+            khill: result.khill(),
+            alpha_entropy: result.alpha_entropy,
+            gamma_entropy: result.gamma_entropy,
+            beta_entropy: result.beta_entropy,
+            alpha_diversity: result.alpha_diversity(),
+            gamma_diversity: result.gamma_diversity(),
+            hill_numbers: result.hill_numbers.iter()
+                .map(|hill| HillNumbersRecord { q: hill.q, alpha: hill.alpha, gamma: hill.gamma, beta: hill.beta })
+                .collect(),
+        };
+
+        let genomes = analysis.sorted_genomes(sort_by).into_iter()
+            .map(|(genome_id, genome_file)| {
+                let components = &result.genome_components[genome_id];
+                let metadata = match groups.metadata(&analysis.group_id, genome_file) {
+                    Some(metadata) => metadata.iter().cloned().map(Some).collect(),
+                    None => vec![None; groups.metadata_columns().len()],
+                };
+                GenomeRecord {
+                    group_id: analysis.group_id.clone(),
+                    genome_id: genome_id.clone(),
+                    beta_entropy: components.weight * components.kl_divergence,
+                    kl_divergence: components.kl_divergence,
+                    weight: components.weight,
+                    entropy: components.entropy,
+                    khill_without_genome: result.leave_one_out_khill[genome_id],
+                    delta_khill: result.khill() - result.leave_one_out_khill[genome_id],
+                    metadata,
+                }
+            })
+            .collect();
+
+        GroupResults { group, genomes }
+    }
+}
+
+/// Results of a run for all groups of genomes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Results {
+    pub parameters: RunParameters,
+    pub metadata_columns: Vec<String>,
+    pub groups: Vec<GroupResults>,
+}
+
+impl Results {
+    /// Write results to the output directory in the specified format.
+    pub fn write(&self, out_dir: &Path, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Tsv => self.write_tsv(out_dir),
+            OutputFormat::Json => self.write_json(out_dir),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => self.write_parquet(out_dir),
+        }
+    }
+
+    /// Write group results to `khill.tsv` and genome results to `genome_entropy.tsv`.
+    fn write_tsv(&self, out_dir: &Path) -> Result<()> {
+        let mut khill_writer = BufWriter::new(create_file(&out_dir.join("khill.tsv"))?);
+        writeln!(khill_writer, "{}", self.group_columns().join("\t"))?;
+        for GroupResults { group, .. } in &self.groups {
+            let hill_values = group.hill_numbers.iter()
+                .map(|hill| format!("\t{}\t{}\t{}", hill.alpha, hill.gamma, hill.beta))
+                .join("");
+            writeln!(khill_writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}",
+                group.group_id,
+                group.num_genomes,
+                group.khill,
+                group.alpha_entropy,
+                group.gamma_entropy,
+                group.beta_entropy,
+                group.alpha_diversity,
+                group.gamma_diversity,
+                hill_values)?;
+        }
+        khill_writer.flush()?;
+
+        let mut genome_writer = BufWriter::new(create_file(&out_dir.join("genome_entropy.tsv"))?);
+        writeln!(genome_writer, "{}", self.genome_columns().join("\t"))?;
+        for genome in self.groups.iter().flat_map(|results| &results.genomes) {
+            let metadata_values = genome.metadata.iter()
+                .map(|value| format!("\t{}", value.as_deref().unwrap_or_default()))
+                .join("");
+            writeln!(genome_writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}",
+                genome.group_id,
+                genome.genome_id,
+                genome.beta_entropy,
+                genome.kl_divergence,
+                genome.weight,
+                genome.entropy,
+                genome.khill_without_genome,
+                genome.delta_khill,
+                metadata_values)?;
+        }
+        genome_writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Write run parameters and results to `khill.json`, with genome results nested within each group.
+    fn write_json(&self, out_dir: &Path) -> Result<()> {
+        let groups: Vec<Value> = self.groups.iter()
+            .map(|results| {
+                let mut group = to_json_object(&results.group)?;
+                let genomes = results.genomes.iter()
+                    .map(|genome| {
+                        let mut record = to_json_object(genome)?;
+                        let metadata: Map<String, Value> = self.metadata_columns.iter().cloned()
+                            .zip(genome.metadata.iter().map(|value| value.clone().map_or(Value::Null, Value::String)))
+                            .collect();
+                        record.insert("metadata".to_string(), Value::Object(metadata));
+                        Ok(Value::Object(record))
+                    })
+                    .collect::<Result<Vec<Value>>>()?;
+                group.insert("genomes".to_string(), Value::Array(genomes));
+                Ok(Value::Object(group))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut document = Map::new();
+        document.insert("parameters".to_string(), serde_json::to_value(&self.parameters)?);
+        document.insert("groups".to_string(), Value::Array(groups));
+
+        let mut writer = BufWriter::new(create_file(&out_dir.join("khill.json"))?);
+        serde_json::to_writer_pretty(&mut writer, &document)?;
+        writeln!(writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Names of columns in tabular group results.
+    fn group_columns(&self) -> Vec<String> {
+        let columns = ["group_id", "num_genomes", "k-hill", "alpha_entropy", "gamma_entropy",
+            "beta_entropy", "alpha_diversity", "gamma_diversity"];
+        let hill_columns = self.parameters.hill_orders.iter()
+            .flat_map(|q| [format!("alpha_q{q}"), format!("gamma_q{q}"), format!("beta_q{q}")]);
+
+        columns.into_iter().map(String::from).chain(hill_columns).collect()
+    }
+
+    /// Names of columns in tabular genome results.
+    fn genome_columns(&self) -> Vec<String> {
+        GENOME_COLUMNS.into_iter().map(String::from).chain(self.metadata_columns.iter().cloned()).collect()
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl Results {
+    /// Write group results to `khill.parquet` and genome results to `genome_entropy.parquet`.
+    fn write_parquet(&self, out_dir: &Path) -> Result<()> {
+        let groups: Vec<&GroupRecord> = self.groups.iter().map(|results| &results.group).collect();
+        let float_column = |value: fn(&GroupRecord) -> f64| -> ArrayRef {
+            Arc::new(Float64Array::from_iter_values(groups.iter().map(|group| value(group))))
+        };
+
+        let mut group_columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(groups.iter().map(|group| &group.group_id))),
+            Arc::new(UInt64Array::from_iter_values(groups.iter().map(|group| group.num_genomes as u64))),
+            float_column(|group| group.khill),
+            float_column(|group| group.alpha_entropy),
+            float_column(|group| group.gamma_entropy),
+            float_column(|group| group.beta_entropy),
+            float_column(|group| group.alpha_diversity),
+            float_column(|group| group.gamma_diversity),
+        ];
+        for order_idx in 0..self.parameters.hill_orders.len() {
+            let hill_numbers = groups.iter().map(|group| &group.hill_numbers[order_idx]);
+            group_columns.push(Arc::new(Float64Array::from_iter_values(hill_numbers.clone().map(|hill| hill.alpha))));
+            group_columns.push(Arc::new(Float64Array::from_iter_values(hill_numbers.clone().map(|hill| hill.gamma))));
+            group_columns.push(Arc::new(Float64Array::from_iter_values(hill_numbers.map(|hill| hill.beta))));
+        }
+        self.write_parquet_table(&out_dir.join("khill.parquet"), &self.group_columns(), group_columns)?;
+
+        let genomes: Vec<&GenomeRecord> = self.groups.iter().flat_map(|results| &results.genomes).collect();
+        let float_column = |value: fn(&GenomeRecord) -> f64| -> ArrayRef {
+            Arc::new(Float64Array::from_iter_values(genomes.iter().map(|genome| value(genome))))
+        };
+
+        let mut genome_columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(genomes.iter().map(|genome| &genome.group_id))),
+            Arc::new(StringArray::from_iter_values(genomes.iter().map(|genome| &genome.genome_id))),
+            float_column(|genome| genome.beta_entropy),
+            float_column(|genome| genome.kl_divergence),
+            float_column(|genome| genome.weight),
+            float_column(|genome| genome.entropy),
+            float_column(|genome| genome.khill_without_genome),
+            float_column(|genome| genome.delta_khill),
+        ];
+        for column_idx in 0..self.metadata_columns.len() {
+            let values = genomes.iter().map(|genome| genome.metadata[column_idx].as_deref());
+            genome_columns.push(Arc::new(values.collect::<StringArray>()));
+        }
+        self.write_parquet_table(&out_dir.join("genome_entropy.parquet"), &self.genome_columns(), genome_columns)?;
+
+        Ok(())
+    }
+
+    /// Write columns to a Parquet file, recording run parameters in the key-value metadata of the file.
+    fn write_parquet_table(&self, path: &Path, column_names: &[String], columns: Vec<ArrayRef>) -> Result<()> {
+        let fields: Vec<Field> = column_names.iter()
+            .zip(&columns)
+            .map(|(name, column)| Field::new(name, column.data_type().clone(), *column.data_type() == DataType::Utf8))
+            .collect();
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?;
+
+        let parameters = to_json_object(&self.parameters)?.into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                KeyValue::new(format!("khill.{key}"), value)
+            })
+            .collect();
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_key_value_metadata(Some(parameters))
+            .build();
+
+        let mut writer = ArrowWriter::try_new(create_file(path)?, batch.schema(), Some(properties))?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        Ok(())
+    }
+}
+
+/// Create an output file.
+fn create_file(path: &Path) -> Result<File> {
+    File::create(path).context(format!("Failed to create {}", path.display()))
+}
+
+/// Serialize order of Hill numbers, with an infinite order written as "inf" since JSON does not support infinity.
+fn serialize_hill_order<S: Serializer>(q: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if q.is_infinite() {
+        serializer.serialize_str("inf")
+    } else {
+        serializer.serialize_f64(*q)
+    }
+}
+
+/// Serialize orders of Hill numbers.
+fn serialize_hill_orders<S: Serializer>(orders: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(orders.len()))?;
+    for q in orders {
+        seq.serialize_element(&HillOrder(*q))?;
+    }
+    seq.end()
+}
+
+/// Order of Hill numbers serialized with `serialize_hill_order`.
+struct HillOrder(f64);
+
+impl Serialize for HillOrder {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_hill_order(&self.0, serializer)
+    }
+}
+
+/// Serialize a record as a JSON object.
+fn to_json_object(record: &impl Serialize) -> Result<Map<String, Value>> {
+    match serde_json::to_value(record)? {
+        Value::Object(object) => Ok(object),
+        _ => unreachable!("records are serialized as JSON objects"),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    #[cfg(feature = "parquet")]
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use tempfile::tempdir;
+
+    pub(crate) fn results() -> Results {
+        let analysis_params = AnalysisParams { hill_orders: vec![0.0, f64::INFINITY], ..Default::default() };
+        let group = |group_id: &str, khill: f64| GroupRecord {
+            group_id: group_id.to_string(),
+            num_genomes: 2,
+            khill,
+            alpha_entropy: 1.0,
+            gamma_entropy: 1.5,
+            beta_entropy: khill.ln(),
+            alpha_diversity: 1.0f64.exp(),
+            gamma_diversity: 1.5f64.exp(),
+            hill_numbers: analysis_params.hill_orders.iter()
+                .map(|&q| HillNumbersRecord { q, alpha: 2.0, gamma: 3.0, beta: 1.5 })
+                .collect(),
+        };
+        let genome = |group_id: &str, genome_id: &str, metadata: Vec<Option<String>>| GenomeRecord {
+            group_id: group_id.to_string(),
+            genome_id: genome_id.to_string(),
+            beta_entropy: 0.25,
+            kl_divergence: 0.5,
+            weight: 0.5,
+            entropy: 1.0,
+            khill_without_genome: 1.0,
+            delta_khill: 0.5,
+            metadata,
+        };
+
+        Results {
+            parameters: RunParameters::new(&SketchParams::new(19, 100, true, true), &analysis_params),
+            metadata_columns: vec!["habitat".to_string()],
+            groups: vec![
+                GroupResults {
+                    group: group("group1", 1.5),
+                    genomes: vec![
+                        genome("group1", "genome1", vec![Some("soil".to_string())]),
+                        genome("group1", "genome2", vec![None]),
+                    ],
+                },
+                GroupResults {
+                    group: group("group2", 2.0),
+                    genomes: vec![genome("group2", "genome3", vec![Some("marine".to_string())])],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_write_tsv() -> Result<()> {
+        let temp_dir = tempdir()?;
+        results().write(temp_dir.path(), OutputFormat::Tsv)?;
+
+        let khill_tsv = std::fs::read_to_string(temp_dir.path().join("khill.tsv"))?;
+        let lines: Vec<&str> = khill_tsv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("group_id\tnum_genomes\tk-hill\t"));
+        assert!(lines[0].ends_with("\talpha_q0\tgamma_q0\tbeta_q0\talpha_qinf\tgamma_qinf\tbeta_qinf"));
+        assert!(lines[1].starts_with("group1\t2\t1.5\t"));
+
+        let genome_tsv = std::fs::read_to_string(temp_dir.path().join("genome_entropy.tsv"))?;
+        let lines: Vec<&str> = genome_tsv.lines().collect();
+        assert_eq!(lines[0], "group_id\tgenome_id\tbeta_entropy\tkl_divergence\tweight\tentropy\tkhill_without_genome\tdelta_khill\thabitat");
+        assert_eq!(lines[1], "group1\tgenome1\t0.25\t0.5\t0.5\t1\t1\t0.5\tsoil");
+        assert_eq!(lines[2], "group1\tgenome2\t0.25\t0.5\t0.5\t1\t1\t0.5\t");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_json() -> Result<()> {
+        let temp_dir = tempdir()?;
+        results().write(temp_dir.path(), OutputFormat::Json)?;
+
+        let document: Value = serde_json::from_reader(File::open(temp_dir.path().join("khill.json"))?)?;
+        assert_eq!(document["parameters"]["kmer_length"], 19);
+        assert_eq!(document["parameters"]["kmer_weighting"], "weighted");
+        assert_eq!(document["groups"].as_array().unwrap().len(), 2);
+
+        let group = &document["groups"][0];
+        assert_eq!(group["group_id"], "group1");
+        assert_eq!(group["k-hill"], 1.5);
+        assert_eq!(document["parameters"]["hill_orders"], serde_json::json!([0.0, "inf"]));
+        assert_eq!(group["hill_numbers"][1]["q"], "inf");
+        assert_eq!(group["genomes"][0]["genome_id"], "genome1");
+        assert_eq!(group["genomes"][0]["metadata"]["habitat"], "soil");
+        assert_eq!(group["genomes"][1]["metadata"]["habitat"], Value::Null);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "parquet")]
+    fn test_write_parquet() -> Result<()> {
+        let temp_dir = tempdir()?;
+        results().write(temp_dir.path(), OutputFormat::Parquet)?;
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(temp_dir.path().join("khill.parquet"))?)?;
+        let key_value_metadata = builder.metadata().file_metadata().key_value_metadata().unwrap();
+        assert!(key_value_metadata.iter().any(|kv| kv.key == "khill.scale" && kv.value.as_deref() == Some("100")));
+        assert!(key_value_metadata.iter().any(|kv| kv.key == "khill.kmer_weighting" && kv.value.as_deref() == Some("weighted")));
+        let batch = builder.build()?.next().unwrap()?;
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.num_columns(), 14);
+        assert_eq!(batch.schema().field(2).name(), "k-hill");
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(temp_dir.path().join("genome_entropy.parquet"))?)?;
+        let batch = builder.build()?.next().unwrap()?;
+        assert_eq!(batch.num_rows(), 3);
+        let habitat = batch.column_by_name("habitat").unwrap();
+        assert_eq!(habitat.null_count(), 1);
+
+        Ok(())
+    }
+}
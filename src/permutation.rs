@@ -0,0 +1,89 @@
+//! This module provides a permutation test for a difference in the K-Hill number of two groups of genomes.
+//!
+//! Genomes from both groups are pooled and randomly reassigned to groups of the original sizes, with
+//! the K-Hill number of each group recalculated from the previously calculated genome hashes. Since
+//! the K-Hill number tends to increase with the number of genomes in a group, keeping group sizes
+//! fixed ensures the observed difference is compared against differences expected for groups of the
+//! same sizes. Each permutation is assigned its own seed derived from a user-specified seed so results
+//! are reproducible regardless of the number of threads used.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::hashing::Hashes;
+use crate::khill::khill_number;
+
+/// Comparison of the K-Hill number of two groups of genomes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupComparison {
+    pub khill_a: f64,
+    pub khill_b: f64,
+    pub difference: f64,
+    pub permutations: usize,
+    pub p_value: f64,
+}
+
+/// Compare the K-Hill number of two groups using a two-sided permutation test.
+///
+/// The p-value is the proportion of permutations, including the observed assignment of genomes,
+/// with an absolute difference in K-Hill at least as large as the observed difference.
+pub fn compare_groups(hashes_a: &[&Hashes], hashes_b: &[&Hashes], permutations: usize, seed: u64) -> GroupComparison {
+    let khill_a = khill_number(hashes_a);
+    let khill_b = khill_number(hashes_b);
+    let difference = khill_a - khill_b;
+
+    let pooled: Vec<&Hashes> = hashes_a.iter().chain(hashes_b).copied().collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let permutation_seeds: Vec<u64> = (0..permutations).map(|_| rng.r#gen()).collect();
+
+    // allow for small differences in floating point error when comparing to the observed difference
+    let threshold = difference.abs() * (1.0 - 1e-12);
+    let num_extreme = permutation_seeds
+        .par_iter()
+        .filter(|&&permutation_seed| {
+            let mut rng = StdRng::seed_from_u64(permutation_seed);
+            let mut permuted = pooled.clone();
+            permuted.shuffle(&mut rng);
+
+            let (permuted_a, permuted_b) = permuted.split_at(hashes_a.len());
+            (khill_number(permuted_a) - khill_number(permuted_b)).abs() >= threshold
+        })
+        .count();
+
+    GroupComparison {
+        khill_a,
+        khill_b,
+        difference,
+        permutations,
+        p_value: (num_extreme + 1) as f64 / (permutations + 1) as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::tests::hashes;
+
+    #[test]
+    fn test_compare_groups() {
+        // group A contains near identical genomes while group B contains distinct genomes
+        let similar: Vec<Hashes> = (0..5).map(|i| hashes(&[(1, 5), (2, 5), (3, 5), (100 + i, 1)])).collect();
+        let distinct: Vec<Hashes> = (0..5).map(|i| hashes(&[(10 * i, 5), (10 * i + 1, 5), (10 * i + 2, 5)])).collect();
+        let group_a: Vec<&Hashes> = similar.iter().collect();
+        let group_b: Vec<&Hashes> = distinct.iter().collect();
+
+        let comparison = compare_groups(&group_a, &group_b, 199, 42);
+        assert_eq!(comparison, compare_groups(&group_a, &group_b, 199, 42));
+        assert_eq!(comparison.permutations, 199);
+        assert!(comparison.khill_a < comparison.khill_b);
+        assert!((comparison.difference - (comparison.khill_a - comparison.khill_b)).abs() < 1e-12);
+        assert!(comparison.p_value < 0.05);
+
+        // groups drawn from the same genomes do not differ
+        let comparison = compare_groups(&group_b[..2], &group_b[2..4], 99, 42);
+        assert!((comparison.difference).abs() < 1e-12);
+        assert_eq!(comparison.p_value, 1.0);
+    }
+}
@@ -0,0 +1,103 @@
+//! This module provides rarefaction curves of the K-Hill number versus the number of genomes in a group.
+//!
+//! For each subset size, genomes are randomly sampled without replacement from the group and the
+//! K-Hill number calculated using the previously calculated genome hashes. Repeating this over
+//! increasing subset sizes shows how K-Hill saturates as genomes are added to a group, which allows
+//! groups with very different numbers of genomes to be compared. Each subset is assigned its own seed
+//! derived from a user-specified seed so results are reproducible regardless of the number of threads used.
+
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::hashing::Hashes;
+use crate::khill::khill_number;
+
+/// K-Hill number of a random subset of genomes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RarefactionPoint {
+    pub num_genomes: usize,
+    pub replicate: usize,
+    pub khill: f64,
+}
+
+/// Determine subset sizes of step, 2*step, ..., ending with the total number of genomes.
+pub fn subset_sizes(num_genomes: usize, step: usize) -> Vec<usize> {
+    let mut sizes: Vec<usize> = (1..)
+        .map(|i| i * step)
+        .take_while(|&size| size < num_genomes)
+        .collect();
+    sizes.push(num_genomes);
+
+    sizes
+}
+
+/// Calculate K-Hill number for random subsets of genomes of increasing size.
+///
+/// Each subset size smaller than the total number of genomes is sampled the specified number
+/// of times. A single replicate is reported when all genomes are included since every subset
+/// would be identical.
+pub fn rarefaction_curve(genome_hashes: &[&Hashes], step: usize, replicates: usize, seed: u64) -> Vec<RarefactionPoint> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut subsets = Vec::new();
+    for size in subset_sizes(genome_hashes.len(), step) {
+        let num_replicates = if size == genome_hashes.len() { 1 } else { replicates };
+        for replicate in 1..=num_replicates {
+            subsets.push((size, replicate, rng.r#gen::<u64>()));
+        }
+    }
+
+    subsets
+        .par_iter()
+        .map(|&(size, replicate, subset_seed)| {
+            let mut rng = StdRng::seed_from_u64(subset_seed);
+            let subset: Vec<&Hashes> = sample(&mut rng, genome_hashes.len(), size)
+                .iter()
+                .map(|idx| genome_hashes[idx])
+                .collect();
+
+            RarefactionPoint {
+                num_genomes: size,
+                replicate,
+                khill: khill_number(&subset),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::tests::hashes;
+
+    #[test]
+    fn test_subset_sizes() {
+        assert_eq!(subset_sizes(10, 3), vec![3, 6, 9, 10]);
+        assert_eq!(subset_sizes(9, 3), vec![3, 6, 9]);
+        assert_eq!(subset_sizes(2, 5), vec![2]);
+    }
+
+    #[test]
+    fn test_rarefaction_curve() {
+        let genome1 = hashes(&[(1, 2), (2, 1), (3, 1)]);
+        let genome2 = hashes(&[(1, 1), (4, 3)]);
+        let genome3 = hashes(&[(2, 2), (5, 1), (6, 1)]);
+        let genomes = vec![&genome1, &genome2, &genome3];
+
+        let curve = rarefaction_curve(&genomes, 1, 4, 42);
+        assert_eq!(curve.len(), 4 + 4 + 1);
+        assert_eq!(curve, rarefaction_curve(&genomes, 1, 4, 42));
+
+        // a single genome always has a K-Hill of 1
+        for point in curve.iter().filter(|p| p.num_genomes == 1) {
+            assert!((point.khill - 1.0).abs() < 1e-12);
+        }
+
+        // all genomes are included in the final point
+        let last = curve.last().unwrap();
+        assert_eq!(last.num_genomes, 3);
+        assert_eq!(last.replicate, 1);
+        assert!((last.khill - khill_number(&genomes)).abs() < 1e-12);
+    }
+}
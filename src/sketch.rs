@@ -0,0 +1,298 @@
+//! This module defines the `Sketch` struct and the on-disk binary format used to persist
+//! FracMinHash sketches between runs. A sketch file contains a short header identifying the
+//! file type, format version, and width of k-mer counts, followed by the sketch parameters, genome identifier,
+//! base pair and k-mer counts, and the retained k-mer hashes with their counts.
+//!
+//! Sketch files allow the K-Hill statistic to be recalculated for different groupings of
+//! genomes without having to re-read and re-hash the original FASTA files.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use needletail::parse_fastx_reader;
+use serde::{Deserialize, Serialize};
+
+use crate::frac_min_hash::FracMinHash;
+use crate::hashing::{Hashes, KmerCount};
+use crate::io_utils::{genome_id_from_filename, Compression};
+use crate::sketch_params::SketchParams;
+
+/// Extension used for sketch files.
+pub const SKETCH_EXT: &str = "khs";
+
+/// Magic bytes identifying a sketch file.
+const SKETCH_MAGIC: &[u8; 4] = b"KHSK";
+
+/// Version of the sketch file format.
+const SKETCH_FORMAT_VERSION: u32 = 3;
+
+/// Number of bytes used to store each k-mer count.
+const KMER_COUNT_BYTES: u8 = std::mem::size_of::<KmerCount>() as u8;
+
+/// FracMinHash sketch of a single genome.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sketch {
+    pub params: SketchParams,
+    pub genome_id: String,
+    pub bp_count: u64,
+    pub kmer_total_count: u64,
+    pub hashes: Hashes,
+}
+
+impl Sketch {
+    /// Create sketch from sequence file.
+    /// 
+    /// Sequence files may be compressed with gzip, bzip2, xz, or zstd. The compression format
+    /// is determined from the magic bytes at the start of the file.
+    pub fn from_seq_file(seq_file: &Path, sketch_params: &SketchParams) -> Result<Self> {
+        let compression = Compression::from_file(seq_file)?;
+        let expected_compression = Compression::from_extension(seq_file);
+        if compression != expected_compression {
+            warn!(
+                "{} has an extension indicating {:?} compression, but its contents indicate {:?} compression.",
+                seq_file.display(),
+                expected_compression,
+                compression
+            );
+        }
+
+        let mut sketcher = sketch_params.create_sketcher();
+        let reader = File::open(seq_file)
+            .context(format!("Failed to open {}", seq_file.display()))?;
+
+        let mut fastx_reader = parse_fastx_reader(reader)?;
+        while let Some(rec) = fastx_reader.next() {
+            let record = rec?;
+            sketcher.process_seq(&record);
+        }
+
+        let genome_id = genome_id_from_filename(seq_file);
+        Ok(Self::from_sketcher(sketcher, genome_id, sketch_params, &seq_file.display().to_string()))
+    }
+
+    /// Create sketch from in-memory nucleotide sequences (e.g. the contigs of a genome).
+    pub fn from_seqs<S: AsRef<[u8]>>(genome_id: &str, seqs: impl IntoIterator<Item = S>, sketch_params: &SketchParams) -> Self {
+        let mut sketcher = sketch_params.create_sketcher();
+        for seq in seqs {
+            sketcher.process_bytes(seq.as_ref());
+        }
+
+        Self::from_sketcher(sketcher, genome_id.to_string(), sketch_params, genome_id)
+    }
+
+    /// Create sketch from a sketcher which has processed all sequences of a genome.
+    fn from_sketcher(sketcher: FracMinHash, genome_id: String, sketch_params: &SketchParams, source: &str) -> Self {
+        if let Some(warning) = saturation_warning(&sketcher, source) {
+            warn!("{}", warning);
+        }
+
+        Sketch {
+            params: sketch_params.clone(),
+            genome_id,
+            bp_count: sketcher.bp_count(),
+            kmer_total_count: sketcher.kmer_total_count(),
+            hashes: sketcher.to_hashes(),
+        }
+    }
+
+    /// Convert sketch into an unweighted sketch recording only the presence of each k-mer.
+    pub fn to_unweighted(mut self) -> Self {
+        self.params = SketchParams::new(self.params.k(), self.params.scale(), false, self.params.skip_ambiguous());
+        self.hashes.values_mut().for_each(|count| *count = 1);
+        self
+    }
+
+    /// Path of sketch file for this genome within the specified directory.
+    pub fn path_in_dir(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.{}", self.genome_id, SKETCH_EXT))
+    }
+
+    /// Write sketch to file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .context(format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        writer.write_all(SKETCH_MAGIC)?;
+        writer.write_all(&SKETCH_FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&[KMER_COUNT_BYTES])?;
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Read sketch from file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .context(format!("Failed to open {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)
+            .context(format!("Failed to read header of {}", path.display()))?;
+        if &magic != SKETCH_MAGIC {
+            bail!("{} is not a khill sketch file", path.display());
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != SKETCH_FORMAT_VERSION {
+            bail!(
+                "{} has sketch format version {}, but version {} is required",
+                path.display(),
+                version,
+                SKETCH_FORMAT_VERSION
+            );
+        }
+
+        let mut count_bytes = [0u8; 1];
+        reader.read_exact(&mut count_bytes)?;
+        if count_bytes[0] != KMER_COUNT_BYTES {
+            bail!(
+                "{} has {}-bit k-mer counts, but this build of khill uses {}-bit k-mer counts",
+                path.display(),
+                8 * count_bytes[0] as u32,
+                8 * KMER_COUNT_BYTES as u32
+            );
+        }
+
+        let sketch = bincode::deserialize_from(&mut reader)
+            .context(format!("Failed to read sketch from {}", path.display()))?;
+
+        Ok(sketch)
+    }
+}
+
+/// Return true if path has the sketch file extension.
+pub fn is_sketch_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == SKETCH_EXT)
+}
+
+/// Warning reported when the counts of hashes in a sketch reached the maximum value of `KmerCount`.
+fn saturation_warning(sketcher: &FracMinHash, source: &str) -> Option<String> {
+    let saturated_hash_count = sketcher.saturated_hash_count();
+    (saturated_hash_count > 0).then(|| format!(
+        "{} hashes in {} reached the maximum k-mer count of {} and were truncated.",
+        saturated_hash_count,
+        source,
+        KmerCount::MAX
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frac_min_hash::tests::saturated_sketcher;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sketch_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fasta_file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&fasta_file, ">seq1\nACGTACGTACGT\n")?;
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let sketch = Sketch::from_seq_file(&fasta_file, &sketch_params)?;
+        assert_eq!(sketch.genome_id, "genome1");
+        assert_eq!(sketch.bp_count, 12);
+        assert_eq!(sketch.kmer_total_count, 10);
+
+        let sketch_file = sketch.path_in_dir(temp_dir.path());
+        assert!(is_sketch_file(&sketch_file));
+        sketch.write(&sketch_file)?;
+
+        let loaded = Sketch::read(&sketch_file)?;
+        assert_eq!(loaded, sketch);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sketch_from_seqs() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fasta_file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&fasta_file, ">seq1\nACGTACGT\nACGT\n>seq2\nGGATCCAAGT\n")?;
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let sketch = Sketch::from_seq_file(&fasta_file, &sketch_params)?;
+        let seqs = [b"ACGTACGTACGT".as_slice(), b"GGATCCAAGT".as_slice()];
+        assert_eq!(Sketch::from_seqs("genome1", seqs, &sketch_params), sketch);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unweighted_sketch() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fasta_file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&fasta_file, ">seq1\nACGTACGTACGT\n")?;
+
+        let weighted = Sketch::from_seq_file(&fasta_file, &SketchParams::new(3, 1, true, true))?;
+        assert!(weighted.hashes.values().any(|&count| count > 1));
+
+        let unweighted = Sketch::from_seq_file(&fasta_file, &SketchParams::new(3, 1, false, true))?;
+        assert!(!unweighted.params.weighted());
+        assert_eq!(unweighted.kmer_total_count, weighted.kmer_total_count);
+        assert_eq!(unweighted.hashes.len(), weighted.hashes.len());
+        assert!(unweighted.hashes.values().all(|&count| count == 1));
+        assert_eq!(weighted.to_unweighted(), unweighted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_saturation_warning() {
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        assert_eq!(saturation_warning(&sketch_params.create_sketcher(), "genome1"), None);
+
+        let sketcher = saturated_sketcher();
+        assert_eq!(
+            saturation_warning(&sketcher, "genome1"),
+            Some(format!("1 hashes in genome1 reached the maximum k-mer count of {} and were truncated.", KmerCount::MAX))
+        );
+
+        let sketch = Sketch::from_sketcher(sketcher, "genome1".to_string(), &sketch_params, "genome1");
+        assert_eq!(sketch.hashes.into_values().collect::<Vec<_>>(), [KmerCount::MAX]);
+    }
+
+    #[test]
+    fn test_sketch_compressed_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fasta = b">seq1\nACGTACGTACGTTTGACA\n>seq2\nGGATCCAAGT\n";
+
+        let fasta_file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&fasta_file, fasta)?;
+
+        let gz_file = temp_dir.path().join("genome1.fna.gz");
+        let mut encoder = flate2::write::GzEncoder::new(File::create(&gz_file)?, flate2::Compression::default());
+        encoder.write_all(fasta)?;
+        encoder.finish()?;
+
+        let zst_file = temp_dir.path().join("genome1.fna.zst");
+        std::fs::write(&zst_file, zstd::encode_all(&fasta[..], 0)?)?;
+
+        let sketch_params = SketchParams::new(5, 1, true, true);
+        let sketch = Sketch::from_seq_file(&fasta_file, &sketch_params)?;
+        for compressed_file in [gz_file, zst_file] {
+            assert_eq!(Sketch::from_seq_file(&compressed_file, &sketch_params)?, sketch);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid_sketch() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let sketch_file = temp_dir.path().join("genome1.khs");
+        std::fs::write(&sketch_file, ">seq1\nACGTACGTACGT\n")?;
+
+        assert!(Sketch::read(&sketch_file).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! This module provides a cache of genome sketches so each genome is sketched only once,
+//! even when it is a member of multiple groups.
+//!
+//! All unique genomes are sketched in parallel up front and their hashes kept in memory.
+//! If a memory budget is specified, sketches which would exceed the budget are instead
+//! spilled to sketch files in a temporary directory and read back whenever they are needed.
+//! Sketches loaded from existing sketch files are never spilled since they can simply be
+//! re-read from their original file.
+//!
+//! Genomes from different files with the same genome identifier are identified by their full path.
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use itertools::Itertools;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rustc_hash::{FxBuildHasher, FxHashMap};
+use tempfile::TempDir;
+
+use crate::hashing::{Hashes, ItemHash, KmerCount};
+use crate::io_utils::{disambiguate_genome_ids, genome_id_from_filename};
+use crate::khill::{load_sketch, FailedGenome};
+use crate::progress::progress_bar;
+use crate::sketch::{is_sketch_file, SKETCH_EXT};
+use crate::sketch_params::SketchParams;
+
+/// Location of the hashes for a cached genome.
+enum CachedHashes {
+    InMemory(Arc<Hashes>),
+    OnDisk(PathBuf),
+}
+
+/// Cached sketch of a genome.
+struct CachedSketch {
+    genome_id: String,
+    hashes: CachedHashes,
+}
+
+/// Cache of genome sketches, keyed by the path of each genome file.
+pub struct SketchCache {
+    sketches: FxHashMap<PathBuf, CachedSketch>,
+    sketch_params: SketchParams,
+    in_memory_bytes: u64,
+    _spill_dir: Option<TempDir>,
+}
+
+impl SketchCache {
+    /// Sketch genomes in parallel, keeping sketches in memory up to the specified memory budget (in bytes).
+    ///
+    /// Genome identifiers explicitly specified for genome files take precedence over those of the sketches.
+    ///
+    /// Spilled sketches are written to a temporary directory created within `spill_parent_dir`.
+    /// Genomes which fail to be sketched are returned along with the reason for the failure.
+    pub fn build(
+        genome_files: &[&PathBuf],
+        genome_ids: &HashMap<PathBuf, String>,
+        sketch_params: &SketchParams,
+        memory_budget: Option<u64>,
+        spill_parent_dir: &Path,
+    ) -> Result<(Self, Vec<FailedGenome>)> {
+        let spill_dir = match memory_budget {
+            Some(_) => Some(tempfile::Builder::new().prefix("khill_sketches").tempdir_in(spill_parent_dir)?),
+            None => None,
+        };
+
+        let in_memory_bytes = AtomicU64::new(0);
+        let progress_bar = progress_bar(genome_files.len() as u64);
+        let sketches: Vec<Result<(PathBuf, CachedSketch), FailedGenome>> = genome_files
+            .par_iter()
+            .map(|&genome_file| {
+                let cached = load_sketch(genome_file, sketch_params).and_then(|sketch| {
+                    let size = hashes_memory(&sketch.hashes);
+                    let within_budget = memory_budget.is_none_or(|budget| {
+                        in_memory_bytes
+                            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                                (used + size <= budget).then_some(used + size)
+                            })
+                            .is_ok()
+                    });
+
+                    let hashes = if within_budget {
+                        CachedHashes::InMemory(Arc::new(sketch.hashes))
+                    } else if is_sketch_file(genome_file) {
+                        CachedHashes::OnDisk(genome_file.clone())
+                    } else {
+                        let spill_dir = spill_dir.as_ref().expect("spill directory exists when a memory budget is set");
+                        let spill_file = spill_dir.path().join(format!("{}.{}", spilled_file_stem(genome_file), SKETCH_EXT));
+                        sketch.write(&spill_file)?;
+                        CachedHashes::OnDisk(spill_file)
+                    };
+
+                    let genome_id = genome_ids.get(genome_file).cloned().unwrap_or(sketch.genome_id);
+                    Ok(CachedSketch { genome_id, hashes })
+                });
+                progress_bar.inc(1);
+
+                cached
+                    .map(|cached| (genome_file.clone(), cached))
+                    .map_err(|error| FailedGenome { genome_file: genome_file.clone(), error })
+            })
+            .collect();
+        progress_bar.finish();
+
+        let mut cache = SketchCache {
+            sketches: FxHashMap::default(),
+            sketch_params: sketch_params.clone(),
+            in_memory_bytes: in_memory_bytes.into_inner(),
+            _spill_dir: spill_dir,
+        };
+
+        let mut failed_genomes = Vec::new();
+        for sketch in sketches {
+            match sketch {
+                Ok((genome_file, cached)) => {
+                    cache.sketches.insert(genome_file, cached);
+                }
+                Err(failed) => failed_genomes.push(failed),
+            }
+        }
+
+        disambiguate_genome_ids(
+            cache.sketches
+                .iter_mut()
+                .map(|(genome_file, cached)| (genome_file.as_path(), &mut cached.genome_id)),
+        );
+
+        Ok((cache, failed_genomes))
+    }
+
+    /// Number of cached sketches.
+    pub fn len(&self) -> usize {
+        self.sketches.len()
+    }
+
+    /// Return true if no sketches are cached.
+    pub fn is_empty(&self) -> bool {
+        self.sketches.is_empty()
+    }
+
+    /// Number of sketches spilled to disk.
+    pub fn spilled_count(&self) -> usize {
+        self.sketches
+            .values()
+            .filter(|cached| matches!(cached.hashes, CachedHashes::OnDisk(_)))
+            .count()
+    }
+
+    /// Approximate memory (in bytes) used by sketches held in memory.
+    pub fn in_memory_bytes(&self) -> u64 {
+        self.in_memory_bytes
+    }
+
+    /// Genome identifier of a cached genome.
+    pub fn genome_id(&self, genome_file: &Path) -> Option<&str> {
+        self.sketches.get(genome_file).map(|cached| cached.genome_id.as_str())
+    }
+
+    /// Genome identifiers of the specified genomes, in the order given and without duplicates.
+    ///
+    /// Genomes which are not in the cache are ignored.
+    pub fn cached_genomes(&self, genome_files: &[PathBuf]) -> Vec<(String, PathBuf)> {
+        genome_files
+            .iter()
+            .unique()
+            .filter_map(|genome_file| {
+                self.genome_id(genome_file).map(|genome_id| (genome_id.to_string(), genome_file.clone()))
+            })
+            .collect()
+    }
+
+    /// Get hashes for the specified genomes, indexed by genome identifier.
+    ///
+    /// Genomes which are not in the cache, such as genomes which failed to be sketched, are ignored.
+    /// Sketches spilled to disk are read back in parallel, with the same conversion of weighted
+    /// sketch files as when the cache was built.
+    pub fn genome_hashes(&self, genome_files: &[PathBuf]) -> Result<FxHashMap<String, Arc<Hashes>>> {
+        genome_files
+            .par_iter()
+            .filter_map(|genome_file| self.sketches.get(genome_file))
+            .map(|cached| {
+                let hashes = match &cached.hashes {
+                    CachedHashes::InMemory(hashes) => Arc::clone(hashes),
+                    CachedHashes::OnDisk(sketch_file) => Arc::new(load_sketch(sketch_file, &self.sketch_params)?.hashes),
+                };
+
+                Ok((cached.genome_id.clone(), hashes))
+            })
+            .collect()
+    }
+}
+
+/// Approximate memory (in bytes) used to store hashes.
+fn hashes_memory(hashes: &Hashes) -> u64 {
+    // each entry requires storage for the key-value pair and a control byte
+    let entry_size = std::mem::size_of::<(ItemHash, KmerCount)>() + 1;
+    (hashes.capacity() * entry_size) as u64
+}
+
+/// Unique file stem for spilled sketch of a genome file.
+///
+/// Genome identifiers are not necessarily unique so a hash of the full path is appended.
+fn spilled_file_stem(genome_file: &Path) -> String {
+    let path_hash = FxBuildHasher.hash_one(genome_file);
+    format!("{}_{:016x}", genome_id_from_filename(genome_file), path_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sketch_cache() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut genome_files = Vec::new();
+        for (idx, seq) in ["ACGTACGTACGT", "ACGTACGTACGA", "TTGACCAGTAGG"].iter().enumerate() {
+            let genome_file = temp_dir.path().join(format!("genome{}.fna", idx + 1));
+            std::fs::write(&genome_file, format!(">seq\n{}\n", seq))?;
+            genome_files.push(genome_file);
+        }
+        let missing = temp_dir.path().join("missing.fna");
+        genome_files.push(missing.clone());
+
+        let sketch_params = SketchParams::new(3, 1, true, true);
+        let files: Vec<&PathBuf> = genome_files.iter().collect();
+
+        // all sketches held in memory
+        let (cache, failed_genomes) = SketchCache::build(&files, &HashMap::new(), &sketch_params, None, temp_dir.path())?;
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.spilled_count(), 0);
+        assert_eq!(failed_genomes.len(), 1);
+        assert_eq!(failed_genomes[0].genome_file, missing);
+
+        // all sketches spilled to disk
+        let (spilled_cache, _) = SketchCache::build(&files, &HashMap::new(), &sketch_params, Some(0), temp_dir.path())?;
+        assert_eq!(spilled_cache.len(), 3);
+        assert_eq!(spilled_cache.spilled_count(), 3);
+        assert_eq!(spilled_cache.in_memory_bytes(), 0);
+
+        // failed genomes are ignored when retrieving hashes
+        let group = &genome_files[1..];
+        let hashes = cache.genome_hashes(group)?;
+        let spilled_hashes = spilled_cache.genome_hashes(group)?;
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes, spilled_hashes);
+        assert!(hashes.contains_key("genome2"));
+        assert!(hashes.contains_key("genome3"));
+        assert_eq!(cache.genome_id(&genome_files[0]), Some("genome1"));
+        assert_eq!(cache.genome_id(&missing), None);
+        let genomes = cache.cached_genomes(&[genome_files[1].clone(), missing.clone(), genome_files[0].clone(), genome_files[1].clone()]);
+        assert_eq!(genomes, [("genome2".to_string(), genome_files[1].clone()), ("genome1".to_string(), genome_files[0].clone())]);
+
+        // genomes with the same identifier are identified by their full path
+        let other_dir = temp_dir.path().join("other");
+        std::fs::create_dir(&other_dir)?;
+        let other_genome1 = other_dir.join("genome1.fna");
+        std::fs::write(&other_genome1, ">seq\nACGTTTGA\n")?;
+        let files = [&genome_files[0], &other_genome1, &genome_files[1]];
+        let (cache, _) = SketchCache::build(&files, &HashMap::new(), &sketch_params, None, temp_dir.path())?;
+        let genome1_id = genome_files[0].display().to_string();
+        let other_genome1_id = other_genome1.display().to_string();
+        assert_eq!(cache.genome_id(&genome_files[0]), Some(genome1_id.as_str()));
+        assert_eq!(cache.genome_id(&other_genome1), Some(other_genome1_id.as_str()));
+        assert_eq!(cache.genome_id(&genome_files[1]), Some("genome2"));
+        assert_eq!(cache.genome_hashes(&[genome_files[0].clone(), other_genome1.clone()])?.len(), 2);
+
+        // explicit genome identifiers take precedence
+        let genome_ids = HashMap::from([(other_genome1.clone(), "GCF_1".to_string())]);
+        let (cache, _) = SketchCache::build(&files, &genome_ids, &sketch_params, None, temp_dir.path())?;
+        assert_eq!(cache.genome_id(&genome_files[0]), Some("genome1"));
+        assert_eq!(cache.genome_id(&other_genome1), Some("GCF_1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spilled_weighted_sketch_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let genome_file = temp_dir.path().join("genome1.fna");
+        std::fs::write(&genome_file, ">seq\nACGTACGTACGTTTGACC\n")?;
+        let sketch_file = temp_dir.path().join(format!("genome1.{}", SKETCH_EXT));
+        crate::sketch::Sketch::from_seq_file(&genome_file, &SketchParams::new(3, 1, true, true))?.write(&sketch_file)?;
+
+        // weighted sketch file used in an unweighted run is converted when re-read from disk
+        let sketch_params = SketchParams::new(3, 1, false, true);
+        let files = [&sketch_file];
+        let (cache, _) = SketchCache::build(&files, &HashMap::new(), &sketch_params, None, temp_dir.path())?;
+        let (spilled_cache, failed_genomes) = SketchCache::build(&files, &HashMap::new(), &sketch_params, Some(0), temp_dir.path())?;
+        assert!(failed_genomes.is_empty());
+        assert_eq!(spilled_cache.spilled_count(), 1);
+
+        let hashes = spilled_cache.genome_hashes(std::slice::from_ref(&sketch_file))?;
+        assert_eq!(hashes, cache.genome_hashes(std::slice::from_ref(&sketch_file))?);
+        assert!(hashes["genome1"].values().all(|&count| count == 1));
+
+        Ok(())
+    }
+}
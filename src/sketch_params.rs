@@ -1,20 +1,30 @@
 
 //! This module defines the `SketchParams` struct, which encapsulates the parameters
 //! required to configure and create FracMinHash sketches, such as k-mer length, scale,
-//! and whether the sketch is weighted. It provides methods for constructing new parameter
+//! whether the sketch is weighted, and whether k-mers containing ambiguous bases are
+//! skipped. It provides methods for constructing new parameter
 //! sets, accessing individual parameters, creating sketchers, and checking compatibility
 //! between different parameter sets.
 
+use std::fmt;
+
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::frac_min_hash::FracMinHash;
 
+/// Shortest supported k-mer length.
+pub const MIN_KMER_LENGTH: u8 = 1;
+
+/// Longest supported k-mer length, limited by the 128-bit encoding of k-mers.
+pub const MAX_KMER_LENGTH: u8 = 64;
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SketchParams {
     kmer_length: u8,
     scale: u64,
     weighted: bool,
+    skip_ambiguous: bool,
 }
 
 impl Default for SketchParams {
@@ -23,21 +33,58 @@ impl Default for SketchParams {
             kmer_length: 31,
             scale: 1000,
             weighted: false,
+            skip_ambiguous: true,
         }
     }
 }
 
 impl SketchParams {
-    pub fn new(kmer_length: u8, scale: u64, weighted: bool) -> Self {
-        SketchParams {
+    /// Create sketch parameters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameters are invalid (see `SketchParams::try_new`).
+    pub fn new(kmer_length: u8, scale: u64, weighted: bool, skip_ambiguous: bool) -> Self {
+        match Self::try_new(kmer_length, scale, weighted, skip_ambiguous) {
+            Ok(params) => params,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Create sketch parameters, returning an error if the k-mer length is not in
+    /// [`MIN_KMER_LENGTH`, `MAX_KMER_LENGTH`] or the scale is 0.
+    pub fn try_new(kmer_length: u8, scale: u64, weighted: bool, skip_ambiguous: bool) -> Result<Self> {
+        Self::check_kmer_length(kmer_length)?;
+        Self::check_scale(scale)?;
+
+        Ok(SketchParams {
             kmer_length,
             scale,
             weighted,
+            skip_ambiguous,
+        })
+    }
+
+    /// Verify that k-mers of the specified length can be sketched.
+    pub fn check_kmer_length(kmer_length: u8) -> Result<()> {
+        if !(MIN_KMER_LENGTH..=MAX_KMER_LENGTH).contains(&kmer_length) {
+            bail!("k-mer length must be in the range [{}, {}], but k = {}", MIN_KMER_LENGTH, MAX_KMER_LENGTH, kmer_length);
+        }
+
+        Ok(())
+    }
+
+    /// Verify that the scale is at least 1.
+    pub fn check_scale(scale: u64) -> Result<()> {
+        if scale == 0 {
+            bail!("Scale must be at least 1");
         }
+
+        Ok(())
     }
 
     pub fn create_sketcher(&self) -> FracMinHash {
-        FracMinHash::new(self.kmer_length, self.scale)
+        FracMinHash::new(self.kmer_length, self.scale, self.weighted, self.skip_ambiguous)
     }
 
     pub fn k(&self) -> u8 {
@@ -52,6 +99,15 @@ impl SketchParams {
         self.weighted
     }
 
+    pub fn skip_ambiguous(&self) -> bool {
+        self.skip_ambiguous
+    }
+
+    /// Name of k-mer weighting mode reported in output files.
+    pub fn weighting(&self) -> &'static str {
+        if self.weighted { "weighted" } else { "unweighted" }
+    }
+
     /// Return true if sketch parameters are identical.
     pub fn check_compatibility(&self, other: &SketchParams) -> Result<bool> {
         if self.k() != other.k() {
@@ -75,8 +131,8 @@ impl SketchParams {
             );
         }
 
-        // It is possible to convert a weighted sketch into an unweighted sketch,
-        // but this conversion is not currently supported.
+        // Weighted sketches read from file are converted into unweighted sketches
+        // when required (see `Sketch::to_unweighted`), but the reverse is not possible.
         if self.weighted() != other.weighted() {
             bail!(
                 "Sketch has weighted = {}, but other sketch has weighted = {}",
@@ -85,6 +141,50 @@ impl SketchParams {
             );
         }
 
+        if self.skip_ambiguous() != other.skip_ambiguous() {
+            bail!(
+                "Sketch has skip_ambiguous = {}, but other sketch has skip_ambiguous = {}",
+                self.skip_ambiguous(),
+                other.skip_ambiguous()
+            );
+        }
+
         Ok(true)
     }
 }
+
+impl fmt::Display for SketchParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ambiguous = if self.skip_ambiguous { "skipped" } else { "treated as A" };
+        write!(
+            f,
+            "k = {}, scale = {}, {} k-mers, k-mers with ambiguous bases {}",
+            self.kmer_length,
+            self.scale,
+            self.weighting(),
+            ambiguous
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_new() {
+        assert!(SketchParams::try_new(19, 100, true, true).is_ok());
+        assert!(SketchParams::try_new(64, 1, true, true).is_ok());
+        assert!(SketchParams::try_new(0, 100, true, true).is_err());
+        assert!(SketchParams::try_new(65, 100, true, true).is_err());
+        assert!(SketchParams::try_new(19, 0, true, true).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            SketchParams::new(19, 100, false, true).to_string(),
+            "k = 19, scale = 100, unweighted k-mers, k-mers with ambiguous bases skipped"
+        );
+    }
+}